@@ -0,0 +1,135 @@
+use std::fs;
+
+use anyhow::{Context, Result};
+use toml::value::{Table, Value};
+
+use crate::config::Config;
+use crate::hotkey::Hotkey;
+use crate::ptt::{self, Action, Chord, ReleaseMode, TraceEvent};
+
+const USAGE: &str = "Usage:
+    mute config list
+    mute config get <key>
+    mute config set <key> <value>
+    mute profile list
+    mute profile use <name>
+    mute profile new <name>
+    mute profile remove <name>
+    mute replay <trace> <hotkey> [any-key|main-key] [<debounce ms>]
+
+key 的格式为 profiles.default.scope, 名字带点时用双引号括起来, 如 devices.\"alsa_input.usb\".strategy
+快捷键写成 Ctrl+Alt+M 这样的字符串, 修饰键有 Ctrl, Alt, Shift, Super
+replay 按按住说话的规则回放按键记录, 每行为 `<毫秒> down|up|repeat <虚拟键码>`";
+
+/// 处理命令行子命令, 没有子命令时返回 false, 继续启动图形界面
+pub fn run(args: &[String]) -> Result<bool> {
+    match args.get(1).map(|it| it.as_str()) {
+        Some("config") => {
+            config(&args[2..])?;
+            Ok(true)
+        }
+        Some("profile") => {
+            profile(&args[2..])?;
+            Ok(true)
+        }
+        Some("replay") => {
+            replay(&args[2..])?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+fn config(args: &[String]) -> Result<()> {
+    let args: Vec<&str> = args.iter().map(|it| it.as_str()).collect();
+
+    match args.as_slice() {
+        ["list"] => {
+            for (key, value) in Config::load()?.list()? {
+                println!("{} = {}", key, value);
+            }
+        }
+        ["get", key] => {
+            let value = Config::load()?.get(key)?;
+            match value {
+                // 方便在脚本中使用, 字符串不加引号
+                Value::String(s) => println!("{}", s),
+                Value::Table(_) => print!("{}", toml::to_string(&value)?),
+                _ => println!("{}", value),
+            }
+        }
+        ["set", key, value] => {
+            let mut config = Config::load()?;
+            config.set(key, parse_value(value))?;
+            config.save()?;
+        }
+        _ => return Err(anyhow::anyhow!("Invalid arguments\n{}", USAGE)),
+    }
+
+    Ok(())
+}
+
+/// 正在运行的程序会监视配置文件, 修改后立即生效
+fn profile(args: &[String]) -> Result<()> {
+    let args: Vec<&str> = args.iter().map(|it| it.as_str()).collect();
+    let mut config = Config::load()?;
+
+    match args.as_slice() {
+        ["list"] => {
+            for name in config.profiles.keys() {
+                let mark = if *name == config.profile { "*" } else { " " };
+                println!("{} {}", mark, name);
+            }
+            return Ok(());
+        }
+        ["use", name] => config.use_profile(name)?,
+        ["new", name] => config.add_profile(name)?,
+        ["remove", name] => config.remove_profile(name)?,
+        _ => return Err(anyhow::anyhow!("Invalid arguments\n{}", USAGE)),
+    }
+
+    config.save()
+}
+
+/// 按 toml 的语法解析值, 解析不了的当作字符串, 这样字符串不需要再加引号
+fn parse_value(s: &str) -> Value {
+    toml::from_str::<Table>(&format!("value = {}", s))
+        .ok()
+        .and_then(|mut it| it.remove("value"))
+        .unwrap_or_else(|| Value::String(s.to_string()))
+}
+
+/// 输出每个动作发生的时间, 用来复现按住说话的问题
+fn replay(args: &[String]) -> Result<()> {
+    let args: Vec<&str> = args.iter().map(|it| it.as_str()).collect();
+
+    let (path, hotkey, mode, debounce) = match args.as_slice() {
+        [path, hotkey] => (path, hotkey, "any-key", "0"),
+        [path, hotkey, mode] => (path, hotkey, *mode, "0"),
+        [path, hotkey, mode, debounce] => (path, hotkey, *mode, *debounce),
+        _ => return Err(anyhow::anyhow!("Invalid arguments\n{}", USAGE)),
+    };
+
+    let hotkey: Hotkey = hotkey.parse()?;
+    let chord = Chord::from_vk(&hotkey)
+        .with_context(|| format!("{} has no virtual key code", hotkey))?;
+    let mode: ReleaseMode = mode.parse()?;
+    let debounce: u64 = debounce.parse().context("invalid debounce")?;
+
+    let text = fs::read_to_string(path).with_context(|| format!("Read {} failed", path))?;
+    let events = text
+        .lines()
+        .map(|it| it.trim())
+        .filter(|it| !it.is_empty() && !it.starts_with('#'))
+        .map(|it| it.parse::<TraceEvent>())
+        .collect::<Result<Vec<_>>>()?;
+
+    for (at, action) in ptt::replay(chord, mode, debounce, &events) {
+        match action {
+            Action::Unmute => println!("{} unmute", at),
+            Action::Mute => println!("{} mute", at),
+        }
+    }
+
+    Ok(())
+}
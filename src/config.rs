@@ -0,0 +1,557 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use gio::prelude::*;
+use gio::{FileMonitor, FileMonitorEvent, FileMonitorFlags};
+use serde::{Deserialize, Serialize};
+use toml::value::{Table, Value};
+
+use crate::hotkey::{Hotkey, Modifiers};
+use crate::ptt::ReleaseMode;
+use crate::volume::ScopeMode;
+
+/// 配置文件格式的版本, 格式不兼容时加一并在 `Config::migrate` 中处理旧版本
+pub const CONFIG_VERSION: u32 = 4;
+
+/// 没有配置方案时使用的方案名
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// 静音的实现方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MuteStrategy {
+    /// 优先使用设备的静音开关, 开关没有生效时把这个设备改为 `Gain`
+    Auto,
+    /// 只使用设备的静音开关
+    Native,
+    /// 记下当前音量后把音量调到 0, 取消静音时恢复
+    Gain,
+}
+
+impl Default for MuteStrategy {
+    fn default() -> Self {
+        MuteStrategy::Auto
+    }
+}
+
+impl MuteStrategy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MuteStrategy::Auto => "auto",
+            MuteStrategy::Native => "native",
+            MuteStrategy::Gain => "gain",
+        }
+    }
+}
+
+impl FromStr for MuteStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(MuteStrategy::Auto),
+            "native" => Ok(MuteStrategy::Native),
+            "gain" => Ok(MuteStrategy::Gain),
+            _ => Err(anyhow::anyhow!("unknown mute strategy: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeviceConfig {
+    #[serde(default)]
+    pub strategy: MuteStrategy,
+
+    /// 音量归零前的音量, 只在处于归零静音状态时存在
+    pub saved_gain: Option<f32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HotkeyConfig {
+    pub enabled: bool,
+    /// 如 `Ctrl+Alt+M`, 为空时使用列表中的第一个键.
+    /// 版本 3 之前这里只有按键, 修饰键单独保存在 modifiers 中
+    #[serde(alias = "key")]
+    pub hotkey: Option<Hotkey>,
+
+    #[serde(skip_serializing)]
+    modifiers: Option<Modifiers>,
+}
+
+impl HotkeyConfig {
+    pub fn new(enabled: bool, hotkey: Option<Hotkey>) -> Self {
+        HotkeyConfig {
+            enabled,
+            hotkey,
+            modifiers: None,
+        }
+    }
+
+    fn migrate(&mut self) {
+        if let (Some(hotkey), Some(modifiers)) = (&mut self.hotkey, self.modifiers.take()) {
+            hotkey.modifiers = modifiers;
+        }
+    }
+}
+
+/// 输入音量的两个快捷键共用开关
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GainHotkeyConfig {
+    pub enabled: bool,
+    pub up: Option<Hotkey>,
+    pub down: Option<Hotkey>,
+
+    #[serde(skip_serializing)]
+    modifiers: Option<Modifiers>,
+}
+
+impl GainHotkeyConfig {
+    pub fn new(enabled: bool, up: Option<Hotkey>, down: Option<Hotkey>) -> Self {
+        GainHotkeyConfig {
+            enabled,
+            up,
+            down,
+            modifiers: None,
+        }
+    }
+
+    fn migrate(&mut self) {
+        if let Some(modifiers) = self.modifiers.take() {
+            for hotkey in self.up.iter_mut().chain(self.down.iter_mut()) {
+                hotkey.modifiers = modifiers;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HotkeysConfig {
+    /// 按住说话什么时候算松开
+    pub push_release: ReleaseMode,
+    /// 松开按住说话后等待多少毫秒再静音, 期间再次按下不会静音
+    pub push_debounce: u64,
+
+    /// 切换静音
+    pub mute: HotkeyConfig,
+    /// 按住说话
+    pub push: HotkeyConfig,
+    /// 按住静音, 松开后恢复按下前的状态
+    pub cough: HotkeyConfig,
+    pub gain: GainHotkeyConfig,
+}
+
+/// 配置方案, 可以按使用场景整体切换
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    /// 选择的输入设备, 为空时跟随系统默认设备
+    pub device: Option<String>,
+
+    /// mute/unmute/toggle 的作用范围
+    pub scope: ScopeMode,
+
+    pub hotkeys: HotkeysConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Config {
+    /// 缺省为 0, 表示加入版本号之前的配置文件
+    #[serde(default)]
+    pub version: u32,
+
+    /// 当前使用的配置方案
+    #[serde(default = "default_profile")]
+    pub profile: String,
+
+    /// 选中程序模式下要静音的程序, 按 application.name 匹配
+    #[serde(default)]
+    pub apps: BTreeSet<String>,
+
+    /// 豁免模式下不静音的程序, 按 application.name 匹配
+    #[serde(default)]
+    pub exempt_apps: BTreeSet<String>,
+
+    /// 选中程序和豁免模式下最近一次操作是否为静音, 重启后新出现的录音流也按这个状态处理
+    #[serde(default)]
+    pub streams_muted: bool,
+
+    /// 依次切换配置方案, 不随方案变化
+    #[serde(default)]
+    pub profile_hotkey: HotkeyConfig,
+
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+
+    /// 以设备 id 为 key 的设置
+    #[serde(default)]
+    pub devices: BTreeMap<String, DeviceConfig>,
+
+    // 版本 1 直接写在顶层的方案设置, 只在升级时读取
+    #[serde(default, rename = "device", skip_serializing)]
+    v1_device: Option<String>,
+    #[serde(default, rename = "scope", skip_serializing)]
+    v1_scope: Option<ScopeMode>,
+    #[serde(default, rename = "hotkeys", skip_serializing)]
+    v1_hotkeys: Option<HotkeysConfig>,
+}
+
+fn default_profile() -> String {
+    DEFAULT_PROFILE.to_string()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let mut profiles = BTreeMap::new();
+        profiles.insert(default_profile(), Profile::default());
+
+        Config {
+            version: CONFIG_VERSION,
+            profile: default_profile(),
+            apps: BTreeSet::new(),
+            exempt_apps: BTreeSet::new(),
+            streams_muted: false,
+            profile_hotkey: HotkeyConfig::default(),
+            profiles,
+            devices: BTreeMap::new(),
+            v1_device: None,
+            v1_scope: None,
+            v1_hotkeys: None,
+        }
+    }
+}
+
+impl Config {
+    /// Linux 下为 `$XDG_CONFIG_HOME/MuteAssistant/config.toml`,
+    /// Windows 下为 `%APPDATA%\MuteAssistant\config.toml`
+    pub fn path() -> Result<PathBuf> {
+        // 测试中读写各自线程的临时目录, 不碰用户的配置
+        #[cfg(test)]
+        return Ok(tests::config_dir().join("config.toml"));
+
+        #[allow(unreachable_code)]
+        let dir = dirs::config_dir().context("Config directory not found")?;
+
+        Ok(dir.join("MuteAssistant").join("config.toml"))
+    }
+
+    /// 文件不存在时返回默认配置
+    pub fn load() -> Result<Config> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Read {} failed", path.display()))?;
+        let config: Config = toml::from_str(&content)
+            .with_context(|| format!("Parse {} failed", path.display()))?;
+
+        config.migrate()
+    }
+
+    /// 把旧版本的配置升级到 `CONFIG_VERSION`, 不认识更新的版本
+    fn migrate(mut self) -> Result<Config> {
+        if self.version > CONFIG_VERSION {
+            return Err(anyhow::anyhow!(
+                "config version {} is newer than supported version {}",
+                self.version,
+                CONFIG_VERSION
+            ));
+        }
+
+        // 版本 0 只有 devices 和 exempt_apps, 其余字段取默认值即可.
+        // 版本 1 的设备, 范围和快捷键移到当前方案中.
+        // 版本 2 的按键为 Windows 虚拟键码, 解析 `Hotkey` 时已经转换.
+        // 版本 3 的修饰键合并到快捷键中
+        let profile = self.profiles.entry(self.profile.clone()).or_default();
+        if let Some(device) = self.v1_device.take() {
+            profile.device = Some(device);
+        }
+        if let Some(scope) = self.v1_scope.take() {
+            profile.scope = scope;
+        }
+        if let Some(hotkeys) = self.v1_hotkeys.take() {
+            profile.hotkeys = hotkeys;
+        }
+
+        for profile in self.profiles.values_mut() {
+            profile.hotkeys.mute.migrate();
+            profile.hotkeys.push.migrate();
+            profile.hotkeys.gain.migrate();
+        }
+        self.profile_hotkey.migrate();
+
+        self.version = CONFIG_VERSION;
+
+        Ok(self)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Create {} failed", dir.display()))?;
+        }
+
+        // 先转换成 Value, 输出时普通的值会排在表之前, 字段顺序不会导致 ValueAfterTable
+        let value = Value::try_from(self).context("Serialize config failed")?;
+        let content = toml::to_string(&value).context("Serialize config failed")?;
+        fs::write(&path, content).with_context(|| format!("Write {} failed", path.display()))
+    }
+
+    /// 监视配置文件的变化, 返回的 FileMonitor 被 drop 后停止监视.
+    /// 自己保存配置时同样会收到通知
+    pub fn watch(f: impl Fn() + 'static) -> Result<FileMonitor> {
+        let file = gio::File::new_for_path(Self::path()?);
+        let monitor = file
+            .monitor_file(FileMonitorFlags::NONE, None::<&gio::Cancellable>)
+            .context("Watch config file failed")?;
+
+        monitor.connect_changed(move |_monitor, _file, _other, event| {
+            // 写入过程中会收到多个 Changed, 只在写完或者文件被替换时处理
+            match event {
+                FileMonitorEvent::ChangesDoneHint | FileMonitorEvent::Created => f(),
+                _ => {}
+            }
+        });
+
+        Ok(monitor)
+    }
+
+    /// 按 `profiles.default.scope` 形式的 key 取出配置项, 没有设置的项也视为找不到
+    pub fn get(&self, key: &str) -> Result<Value> {
+        let root = Value::try_from(self).context("Serialize config failed")?;
+
+        let mut value = &root;
+        for part in split_key(key)? {
+            value = value
+                .get(part.as_str())
+                .with_context(|| format!("config key not found: {}", key))?;
+        }
+
+        Ok(value.clone())
+    }
+
+    /// 修改后的配置需要能和配置文件一样被解析, 否则返回错误且不做任何修改
+    pub fn set(&mut self, key: &str, value: Value) -> Result<()> {
+        let parts = split_key(key)?;
+        let (last, tables) = parts.split_last().context("empty config key")?;
+
+        let mut root = Value::try_from(&*self).context("Serialize config failed")?;
+        let mut table = root.as_table_mut().context("config is not a table")?;
+        for part in tables {
+            table = table
+                .entry(part.clone())
+                .or_insert_with(|| Value::Table(Table::new()))
+                .as_table_mut()
+                .with_context(|| format!("config key is not a table: {}", part))?;
+        }
+        table.insert(last.clone(), value);
+
+        let config: Config = root
+            .try_into()
+            .with_context(|| format!("invalid value for {}", key))?;
+        // 不认识的字段在解析时会被忽略
+        if config.get(key).is_err() {
+            return Err(anyhow::anyhow!("unknown config key: {}", key));
+        }
+
+        *self = config;
+        Ok(())
+    }
+
+    /// 所有已设置的配置项, key 的格式和 `get`/`set` 相同
+    pub fn list(&self) -> Result<Vec<(String, Value)>> {
+        let root = Value::try_from(self).context("Serialize config failed")?;
+
+        let mut items = Vec::new();
+        flatten("", &root, &mut items);
+
+        Ok(items)
+    }
+
+    /// 当前使用的配置方案
+    pub fn current(&self) -> Profile {
+        self.profiles.get(&self.profile).cloned().unwrap_or_default()
+    }
+
+    pub fn current_mut(&mut self) -> &mut Profile {
+        self.profiles.entry(self.profile.clone()).or_default()
+    }
+
+    pub fn use_profile(&mut self, name: &str) -> Result<()> {
+        if !self.profiles.contains_key(name) {
+            return Err(anyhow::anyhow!("profile not found: {}", name));
+        }
+
+        self.profile = name.to_string();
+        Ok(())
+    }
+
+    /// 以当前方案为模板新建方案, 不切换过去
+    pub fn add_profile(&mut self, name: &str) -> Result<()> {
+        if name.is_empty() {
+            return Err(anyhow::anyhow!("profile name is empty"));
+        }
+        if self.profiles.contains_key(name) {
+            return Err(anyhow::anyhow!("profile already exists: {}", name));
+        }
+
+        let profile = self.current();
+        self.profiles.insert(name.to_string(), profile);
+        Ok(())
+    }
+
+    pub fn remove_profile(&mut self, name: &str) -> Result<()> {
+        if name == self.profile {
+            return Err(anyhow::anyhow!("cannot remove the active profile: {}", name));
+        }
+
+        self.profiles
+            .remove(name)
+            .with_context(|| format!("profile not found: {}", name))?;
+        Ok(())
+    }
+
+    /// 按名字排序的下一个方案, 用于快捷键和托盘菜单依次切换
+    pub fn next_profile(&self) -> String {
+        self.profiles
+            .keys()
+            .skip_while(|it| **it != self.profile)
+            .nth(1)
+            .or_else(|| self.profiles.keys().next())
+            .cloned()
+            .unwrap_or_else(default_profile)
+    }
+
+    pub fn device(&self, id: &str) -> DeviceConfig {
+        self.devices.get(id).cloned().unwrap_or_default()
+    }
+
+    pub fn device_mut(&mut self, id: &str) -> &mut DeviceConfig {
+        self.devices.entry(id.to_string()).or_default()
+    }
+}
+
+/// 拆分 `devices."alsa_input.usb".strategy` 形式的 key, 带点的名字需要用双引号括起来
+fn split_key(key: &str) -> Result<Vec<String>> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut quoted = false;
+
+    for c in key.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            '.' if !quoted => parts.push(std::mem::take(&mut part)),
+            _ => part.push(c),
+        }
+    }
+    parts.push(part);
+
+    if quoted || parts.iter().any(|it| it.is_empty()) {
+        return Err(anyhow::anyhow!("invalid config key: {}", key));
+    }
+
+    Ok(parts)
+}
+
+fn join_key(prefix: &str, name: &str) -> String {
+    let bare = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    let name = if bare {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name)
+    };
+
+    if prefix.is_empty() {
+        name
+    } else {
+        format!("{}.{}", prefix, name)
+    }
+}
+
+fn flatten(prefix: &str, value: &Value, items: &mut Vec<(String, Value)>) {
+    match value {
+        Value::Table(table) => {
+            for (name, value) in table {
+                flatten(&join_key(prefix, name), value, items);
+            }
+        }
+        _ => items.push((prefix.to_string(), value.clone())),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// 每个测试在单独的线程中运行, 按线程区分目录
+    pub fn config_dir() -> PathBuf {
+        let thread = format!("{:?}", std::thread::current().id());
+        let name: String = thread.chars().filter(|c| c.is_ascii_digit()).collect();
+
+        std::env::temp_dir()
+            .join(format!("mute-test-{}", std::process::id()))
+            .join(name)
+    }
+
+    fn sample() -> Config {
+        let mut config = Config::default();
+        config.apps.insert("zoom".to_string());
+        config.exempt_apps.insert("OBS".to_string());
+        config.streams_muted = true;
+        config.profile_hotkey = HotkeyConfig::new(true, "Ctrl+Alt+P".parse().ok());
+        config.device_mut("alsa_input.usb").strategy = MuteStrategy::Gain;
+        config.device_mut("alsa_input.usb").saved_gain = Some(0.5);
+
+        let profile = config.current_mut();
+        profile.device = Some("alsa_input.usb".to_string());
+        profile.scope = ScopeMode::Exempt;
+        profile.hotkeys.mute = HotkeyConfig::new(true, "Ctrl+Alt+M".parse().ok());
+        profile.hotkeys.push = HotkeyConfig::new(true, "F13".parse().ok());
+        profile.hotkeys.push_release = ReleaseMode::MainKey;
+        profile.hotkeys.push_debounce = 30;
+        profile.hotkeys.cough = HotkeyConfig::new(false, "Super+Pause".parse().ok());
+        profile.hotkeys.gain =
+            GainHotkeyConfig::new(true, "Ctrl+Up".parse().ok(), "Ctrl+Down".parse().ok());
+
+        config.add_profile("stream").unwrap();
+        config
+    }
+
+    #[test]
+    fn save_then_load() {
+        let config = sample();
+        config.save().unwrap();
+
+        assert_eq!(Config::load().unwrap(), config);
+    }
+
+    #[test]
+    fn set_then_load() {
+        let mut config = sample();
+        config.set("profiles.default.hotkeys.push_debounce", Value::Integer(50)).unwrap();
+        config.save().unwrap();
+
+        let loaded = Config::load().unwrap();
+        assert_eq!(loaded.current().hotkeys.push_debounce, 50);
+        assert_eq!(loaded, config);
+    }
+
+    #[test]
+    fn set_rejects_invalid_value() {
+        let mut config = sample();
+        let value = Value::String("sometimes".to_string());
+
+        assert!(config.set("profiles.default.hotkeys.push_release", value).is_err());
+        assert_eq!(config, sample());
+    }
+}
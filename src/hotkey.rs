@@ -0,0 +1,506 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// 定义 `Key` 以及它在各个平台上的编码.
+/// 每行依次为名字, Windows 虚拟键码, X11 keysym, evdev 键码, 平台上没有对应的键时为 None
+macro_rules! keys {
+    ($($key:ident = $name:expr, $vk:expr, $keysym:expr, $evdev:expr;)*) => {
+        /// 与平台无关的按键, 不包括修饰键
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub enum Key {
+            $($key,)*
+        }
+
+        impl Key {
+            pub const ALL: &'static [Key] = &[$(Key::$key,)*];
+
+            /// 配置文件和界面中使用的名字
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Key::$key => $name,)*
+                }
+            }
+
+            pub fn to_vk(self) -> Option<u32> {
+                match self {
+                    $(Key::$key => $vk,)*
+                }
+            }
+
+            pub fn to_keysym(self) -> Option<u32> {
+                match self {
+                    $(Key::$key => $keysym,)*
+                }
+            }
+
+            pub fn to_evdev(self) -> Option<u16> {
+                match self {
+                    $(Key::$key => $evdev,)*
+                }
+            }
+        }
+    };
+}
+
+keys! {
+    A = "A", Some(0x41), Some(0x61), Some(30);
+    B = "B", Some(0x42), Some(0x62), Some(48);
+    C = "C", Some(0x43), Some(0x63), Some(46);
+    D = "D", Some(0x44), Some(0x64), Some(32);
+    E = "E", Some(0x45), Some(0x65), Some(18);
+    F = "F", Some(0x46), Some(0x66), Some(33);
+    G = "G", Some(0x47), Some(0x67), Some(34);
+    H = "H", Some(0x48), Some(0x68), Some(35);
+    I = "I", Some(0x49), Some(0x69), Some(23);
+    J = "J", Some(0x4A), Some(0x6A), Some(36);
+    K = "K", Some(0x4B), Some(0x6B), Some(37);
+    L = "L", Some(0x4C), Some(0x6C), Some(38);
+    M = "M", Some(0x4D), Some(0x6D), Some(50);
+    N = "N", Some(0x4E), Some(0x6E), Some(49);
+    O = "O", Some(0x4F), Some(0x6F), Some(24);
+    P = "P", Some(0x50), Some(0x70), Some(25);
+    Q = "Q", Some(0x51), Some(0x71), Some(16);
+    R = "R", Some(0x52), Some(0x72), Some(19);
+    S = "S", Some(0x53), Some(0x73), Some(31);
+    T = "T", Some(0x54), Some(0x74), Some(20);
+    U = "U", Some(0x55), Some(0x75), Some(22);
+    V = "V", Some(0x56), Some(0x76), Some(47);
+    W = "W", Some(0x57), Some(0x77), Some(17);
+    X = "X", Some(0x58), Some(0x78), Some(45);
+    Y = "Y", Some(0x59), Some(0x79), Some(21);
+    Z = "Z", Some(0x5A), Some(0x7A), Some(44);
+    Digit0 = "0", Some(0x30), Some(0x30), Some(11);
+    Digit1 = "1", Some(0x31), Some(0x31), Some(2);
+    Digit2 = "2", Some(0x32), Some(0x32), Some(3);
+    Digit3 = "3", Some(0x33), Some(0x33), Some(4);
+    Digit4 = "4", Some(0x34), Some(0x34), Some(5);
+    Digit5 = "5", Some(0x35), Some(0x35), Some(6);
+    Digit6 = "6", Some(0x36), Some(0x36), Some(7);
+    Digit7 = "7", Some(0x37), Some(0x37), Some(8);
+    Digit8 = "8", Some(0x38), Some(0x38), Some(9);
+    Digit9 = "9", Some(0x39), Some(0x39), Some(10);
+    F1 = "F1", Some(0x70), Some(0xFFBE), Some(59);
+    F2 = "F2", Some(0x71), Some(0xFFBF), Some(60);
+    F3 = "F3", Some(0x72), Some(0xFFC0), Some(61);
+    F4 = "F4", Some(0x73), Some(0xFFC1), Some(62);
+    F5 = "F5", Some(0x74), Some(0xFFC2), Some(63);
+    F6 = "F6", Some(0x75), Some(0xFFC3), Some(64);
+    F7 = "F7", Some(0x76), Some(0xFFC4), Some(65);
+    F8 = "F8", Some(0x77), Some(0xFFC5), Some(66);
+    F9 = "F9", Some(0x78), Some(0xFFC6), Some(67);
+    F10 = "F10", Some(0x79), Some(0xFFC7), Some(68);
+    F11 = "F11", Some(0x7A), Some(0xFFC8), Some(87);
+    F12 = "F12", Some(0x7B), Some(0xFFC9), Some(88);
+    F13 = "F13", Some(0x7C), Some(0xFFCA), Some(183);
+    F14 = "F14", Some(0x7D), Some(0xFFCB), Some(184);
+    F15 = "F15", Some(0x7E), Some(0xFFCC), Some(185);
+    F16 = "F16", Some(0x7F), Some(0xFFCD), Some(186);
+    F17 = "F17", Some(0x80), Some(0xFFCE), Some(187);
+    F18 = "F18", Some(0x81), Some(0xFFCF), Some(188);
+    F19 = "F19", Some(0x82), Some(0xFFD0), Some(189);
+    F20 = "F20", Some(0x83), Some(0xFFD1), Some(190);
+    F21 = "F21", Some(0x84), Some(0xFFD2), Some(191);
+    F22 = "F22", Some(0x85), Some(0xFFD3), Some(192);
+    F23 = "F23", Some(0x86), Some(0xFFD4), Some(193);
+    F24 = "F24", Some(0x87), Some(0xFFD5), Some(194);
+    Space = "Space", Some(0x20), Some(0x20), Some(57);
+    Enter = "Enter", Some(0x0D), Some(0xFF0D), Some(28);
+    Tab = "Tab", Some(0x09), Some(0xFF09), Some(15);
+    Escape = "Escape", Some(0x1B), Some(0xFF1B), Some(1);
+    Backspace = "Backspace", Some(0x08), Some(0xFF08), Some(14);
+    Insert = "Insert", Some(0x2D), Some(0xFF63), Some(110);
+    Delete = "Delete", Some(0x2E), Some(0xFFFF), Some(111);
+    Home = "Home", Some(0x24), Some(0xFF50), Some(102);
+    End = "End", Some(0x23), Some(0xFF57), Some(107);
+    PageUp = "PageUp", Some(0x21), Some(0xFF55), Some(104);
+    PageDown = "PageDown", Some(0x22), Some(0xFF56), Some(109);
+    Left = "Left", Some(0x25), Some(0xFF51), Some(105);
+    Up = "Up", Some(0x26), Some(0xFF52), Some(103);
+    Right = "Right", Some(0x27), Some(0xFF53), Some(106);
+    Down = "Down", Some(0x28), Some(0xFF54), Some(108);
+    PrintScreen = "PrintScreen", Some(0x2C), Some(0xFF61), Some(99);
+    ScrollLock = "ScrollLock", Some(0x91), Some(0xFF14), Some(70);
+    Pause = "Pause", Some(0x13), Some(0xFF13), Some(119);
+    CapsLock = "CapsLock", Some(0x14), Some(0xFFE5), Some(58);
+    NumLock = "NumLock", Some(0x90), Some(0xFF7F), Some(69);
+    Menu = "Menu", Some(0x5D), Some(0xFF67), Some(127);
+    Numpad0 = "Num0", Some(0x60), Some(0xFFB0), Some(82);
+    Numpad1 = "Num1", Some(0x61), Some(0xFFB1), Some(79);
+    Numpad2 = "Num2", Some(0x62), Some(0xFFB2), Some(80);
+    Numpad3 = "Num3", Some(0x63), Some(0xFFB3), Some(81);
+    Numpad4 = "Num4", Some(0x64), Some(0xFFB4), Some(75);
+    Numpad5 = "Num5", Some(0x65), Some(0xFFB5), Some(76);
+    Numpad6 = "Num6", Some(0x66), Some(0xFFB6), Some(77);
+    Numpad7 = "Num7", Some(0x67), Some(0xFFB7), Some(71);
+    Numpad8 = "Num8", Some(0x68), Some(0xFFB8), Some(72);
+    Numpad9 = "Num9", Some(0x69), Some(0xFFB9), Some(73);
+    NumpadMultiply = "NumMultiply", Some(0x6A), Some(0xFFAA), Some(55);
+    NumpadAdd = "NumAdd", Some(0x6B), Some(0xFFAB), Some(78);
+    NumpadSubtract = "NumSubtract", Some(0x6D), Some(0xFFAD), Some(74);
+    NumpadDecimal = "NumDecimal", Some(0x6E), Some(0xFFAE), Some(83);
+    NumpadDivide = "NumDivide", Some(0x6F), Some(0xFFAF), Some(98);
+    VolumeMute = "VolumeMute", Some(0xAD), Some(0x1008FF12), Some(113);
+    VolumeDown = "VolumeDown", Some(0xAE), Some(0x1008FF11), Some(114);
+    VolumeUp = "VolumeUp", Some(0xAF), Some(0x1008FF13), Some(115);
+    MediaNext = "MediaNext", Some(0xB0), Some(0x1008FF17), Some(163);
+    MediaPrevious = "MediaPrevious", Some(0xB1), Some(0x1008FF16), Some(165);
+    MediaStop = "MediaStop", Some(0xB2), Some(0x1008FF15), Some(166);
+    MediaPlayPause = "MediaPlayPause", Some(0xB3), Some(0x1008FF14), Some(164);
+    MicMute = "MicMute", None, Some(0x1008FFB2), Some(248);
+    Mouse4 = "Mouse4", Some(0x05), None, Some(275);
+    Mouse5 = "Mouse5", Some(0x06), None, Some(276);
+}
+
+impl Key {
+    pub fn from_vk(vk: u32) -> Option<Key> {
+        Key::ALL.iter().copied().find(|key| key.to_vk() == Some(vk))
+    }
+
+    pub fn from_keysym(keysym: u32) -> Option<Key> {
+        Key::ALL.iter().copied().find(|key| key.to_keysym() == Some(keysym))
+    }
+
+    pub fn from_evdev(code: u16) -> Option<Key> {
+        Key::ALL.iter().copied().find(|key| key.to_evdev() == Some(code))
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// 解析时额外接受的常见写法
+const KEY_ALIASES: &[(&str, Key)] = &[
+    ("Esc", Key::Escape),
+    ("Return", Key::Enter),
+    ("Ins", Key::Insert),
+    ("Del", Key::Delete),
+    ("PgUp", Key::PageUp),
+    ("PgDn", Key::PageDown),
+    ("Print", Key::PrintScreen),
+    ("Apps", Key::Menu),
+];
+
+impl FromStr for Key {
+    type Err = anyhow::Error;
+
+    /// 名字不区分大小写
+    fn from_str(s: &str) -> Result<Self> {
+        let names = Key::ALL.iter().map(|&key| (key.name(), key));
+
+        names
+            .chain(KEY_ALIASES.iter().copied())
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|(_, key)| key)
+            .ok_or_else(|| anyhow::anyhow!("unknown key: {}", s))
+    }
+}
+
+/// 快捷键的修饰键, Win 在 Linux 下对应 Super
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub win: bool,
+}
+
+impl Modifiers {
+    /// RegisterHotKey 的 fsModifiers, 总是带上 MOD_NOREPEAT
+    pub fn to_win32(self) -> u32 {
+        let mut mods = 0x4000;
+        if self.alt {
+            mods |= 0x0001;
+        }
+        if self.ctrl {
+            mods |= 0x0002;
+        }
+        if self.shift {
+            mods |= 0x0004;
+        }
+        if self.win {
+            mods |= 0x0008;
+        }
+
+        mods
+    }
+
+    /// 按下修饰键时需要检查的虚拟键码, 每一项中任意一个键按下即可
+    pub fn to_vk(self) -> Vec<&'static [u32]> {
+        let mut codes: Vec<&'static [u32]> = Vec::new();
+        if self.ctrl {
+            codes.push(&[0x11]);
+        }
+        if self.alt {
+            codes.push(&[0x12]);
+        }
+        if self.shift {
+            codes.push(&[0x10]);
+        }
+        if self.win {
+            codes.push(&[0x5B, 0x5C]);
+        }
+
+        codes
+    }
+
+    /// X11 的修饰键掩码, Alt 为 Mod1, Super 为 Mod4
+    pub fn to_x11(self) -> u16 {
+        let mut mask = 0;
+        if self.shift {
+            mask |= 0x0001;
+        }
+        if self.ctrl {
+            mask |= 0x0004;
+        }
+        if self.alt {
+            mask |= 0x0008;
+        }
+        if self.win {
+            mask |= 0x0040;
+        }
+
+        mask
+    }
+
+    /// 每一项为左右两个修饰键的 evdev 键码, 任意一个按下即可
+    pub fn to_evdev(self) -> Vec<[u16; 2]> {
+        let mut codes = Vec::new();
+        if self.ctrl {
+            codes.push([29, 97]);
+        }
+        if self.alt {
+            codes.push([56, 100]);
+        }
+        if self.shift {
+            codes.push([42, 54]);
+        }
+        if self.win {
+            codes.push([125, 126]);
+        }
+
+        codes
+    }
+}
+
+/// 修饰键加上一个按键
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Hotkey {
+    pub modifiers: Modifiers,
+    pub key: Key,
+}
+
+/// 系统保留, 注册了也收不到或者会覆盖系统功能的组合
+const RESERVED: &[&str] = &[
+    "Ctrl+Alt+Delete",
+    "Ctrl+Esc",
+    "Ctrl+Shift+Esc",
+    "Alt+Tab",
+    "Alt+Esc",
+    "Alt+F4",
+    "Super+D",
+    "Super+L",
+    "Super+Tab",
+    // Windows 保留给调试器
+    "F12",
+];
+
+impl Hotkey {
+    pub fn new(modifiers: Modifiers, key: Key) -> Self {
+        Hotkey { modifiers, key }
+    }
+
+    /// 不适合作为全局快捷键的原因, 可以使用时返回 None
+    pub fn reserved(&self) -> Option<&'static str> {
+        if RESERVED.iter().any(|it| it.parse().ok() == Some(*self)) {
+            return Some("是系统保留的快捷键");
+        }
+
+        // 没有修饰键或者只有 Shift 时会吞掉正常的输入
+        let Modifiers { ctrl, alt, win, .. } = self.modifiers;
+        let typing = match self.key {
+            Key::Space | Key::Enter | Key::Tab | Key::Backspace | Key::Escape => true,
+            key => key.name().len() == 1,
+        };
+        if typing && !(ctrl || alt || win) {
+            return Some("会妨碍正常输入");
+        }
+
+        None
+    }
+}
+
+/// 格式为 `Ctrl+Alt+Shift+Super+按键`, 和 `FromStr` 互为逆操作
+impl fmt::Display for Hotkey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let modifiers = [
+            (self.modifiers.ctrl, "Ctrl"),
+            (self.modifiers.alt, "Alt"),
+            (self.modifiers.shift, "Shift"),
+            (self.modifiers.win, "Super"),
+        ];
+        for &(_, name) in modifiers.iter().filter(|(on, _)| *on) {
+            write!(f, "{}+", name)?;
+        }
+
+        f.write_str(self.key.name())
+    }
+}
+
+impl FromStr for Hotkey {
+    type Err = anyhow::Error;
+
+    /// 修饰键可以是任意顺序, 不区分大小写. Win 和 Meta 都视为 Super
+    fn from_str(s: &str) -> Result<Self> {
+        let mut modifiers = Modifiers::default();
+        let mut key = None;
+
+        for part in s.split('+').map(str::trim) {
+            if part.is_empty() {
+                return Err(anyhow::anyhow!("empty key in hotkey: {}", s));
+            }
+
+            let modifier = match part.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => Some(&mut modifiers.ctrl),
+                "alt" => Some(&mut modifiers.alt),
+                "shift" => Some(&mut modifiers.shift),
+                "super" | "win" | "meta" => Some(&mut modifiers.win),
+                _ => None,
+            };
+
+            match modifier {
+                Some(modifier) if *modifier => {
+                    return Err(anyhow::anyhow!("duplicate {} in hotkey: {}", part, s));
+                }
+                Some(modifier) => *modifier = true,
+                None if key.is_some() => {
+                    return Err(anyhow::anyhow!("more than one key in hotkey: {}", s));
+                }
+                None => {
+                    let parsed = part
+                        .parse::<Key>()
+                        .with_context(|| format!("invalid hotkey: {}", s))?;
+                    key = Some(parsed);
+                }
+            }
+        }
+
+        let key = key.with_context(|| format!("missing key in hotkey: {}", s))?;
+        Ok(Hotkey::new(modifiers, key))
+    }
+}
+
+impl Serialize for Hotkey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Hotkey {
+    /// 除了 `Ctrl+Alt+M` 这样的字符串, 也接受版本 2 的配置文件中保存的 Windows 虚拟键码
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct HotkeyVisitor;
+
+        impl<'de> Visitor<'de> for HotkeyVisitor {
+            type Value = Hotkey;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a hotkey like \"Ctrl+Alt+M\" or a virtual-key code")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Hotkey, E> {
+                v.parse().map_err(|e| E::custom(format!("{:#}", e)))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Hotkey, E> {
+                Key::from_vk(v as u32)
+                    .map(|key| Hotkey::new(Modifiers::default(), key))
+                    .ok_or_else(|| E::custom(format!("unknown key code: {}", v)))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Hotkey, E> {
+                self.visit_i64(v as i64)
+            }
+        }
+
+        deserializer.deserialize_any(HotkeyVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_then_parse() {
+        let all = Modifiers {
+            ctrl: true,
+            alt: true,
+            shift: true,
+            win: true,
+        };
+        let modifiers = [Modifiers::default(), all];
+
+        for &key in Key::ALL {
+            for &modifiers in &modifiers {
+                let hotkey = Hotkey::new(modifiers, key);
+                assert_eq!(hotkey.to_string().parse::<Hotkey>().unwrap(), hotkey);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_then_format() {
+        let cases = [
+            ("Ctrl+Alt+M", "Ctrl+Alt+M"),
+            ("alt + ctrl + m", "Ctrl+Alt+M"),
+            ("Win+Shift+F13", "Shift+Super+F13"),
+            ("Meta+Esc", "Super+Escape"),
+            ("control+pgdn", "Ctrl+PageDown"),
+            ("Pause", "Pause"),
+        ];
+
+        for &(input, output) in &cases {
+            assert_eq!(input.parse::<Hotkey>().unwrap().to_string(), output);
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        for &input in &["", "Ctrl+", "Ctrl+Ctrl+M", "Ctrl+M+N", "Ctrl+Alt", "Ctrl+Nope"] {
+            assert!(input.parse::<Hotkey>().is_err(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn platform_codes_round_trip() {
+        for &key in Key::ALL {
+            if let Some(vk) = key.to_vk() {
+                assert_eq!(Key::from_vk(vk), Some(key));
+            }
+            if let Some(keysym) = key.to_keysym() {
+                assert_eq!(Key::from_keysym(keysym), Some(key));
+            }
+            if let Some(code) = key.to_evdev() {
+                assert_eq!(Key::from_evdev(code), Some(key));
+            }
+        }
+    }
+
+    #[test]
+    fn reserved_hotkeys() {
+        for &input in &["Alt+F4", "Super+L", "F12", "M", "Shift+Space"] {
+            assert!(input.parse::<Hotkey>().unwrap().reserved().is_some(), "{}", input);
+        }
+        for &input in &["Ctrl+Alt+M", "F13", "Shift+F1", "MicMute"] {
+            assert!(input.parse::<Hotkey>().unwrap().reserved().is_none(), "{}", input);
+        }
+    }
+}
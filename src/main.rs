@@ -1,5 +1,6 @@
 //#![windows_subsystem = "windows"]
 
+use std::cell::RefCell;
 use std::rc::Rc;
 
 use anyhow::Result;
@@ -7,47 +8,65 @@ use gio::prelude::*;
 use gtk::Builder;
 use gtk::prelude::*;
 
+use crate::config::Config;
 use crate::utils::MAIN_VIEW;
 use crate::view::{View, ViewExt};
-use crate::volume::Volume;
+use crate::volume::{GainFallback, MicBackend, MuteScope};
 
+mod cli;
+mod config;
+mod hotkey;
+mod ptt;
 mod utils;
 mod view;
 mod volume;
 
 fn main() -> Result<()> {
-    let volume = Rc::new(Volume::new()?);
+    let args: Vec<String> = std::env::args().collect();
+    if cli::run(&args)? {
+        return Ok(());
+    }
+
+    let config = Config::load().unwrap_or_else(|e| {
+        eprintln!("Load config failed, use default: {:#}", e);
+        Config::default()
+    });
+    let config = Rc::new(RefCell::new(config));
+
+    let backend = Rc::new(GainFallback::new(volume::new_backend()?, config.clone()));
+    let volume = Rc::new(MuteScope::new(backend, config.clone()));
 
     let ui = gtk::Application::new(Some("pw.boot.mute"), gio::ApplicationFlags::FLAGS_NONE)?;
     ui.connect_startup(move |app| {
         let glade = include_str!("../mute.glade");
         let builder = Builder::new_from_string(glade);
 
-        let view = View::new(&builder, volume.clone()).expect("view");
+        let view = View::new(&builder, volume.clone(), config.clone()).expect("view");
         view.init(app);
         unsafe { MAIN_VIEW = Some(view.clone()) };
 
+        let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+        if let Err(e) = volume.subscribe(sender) {
+            eprintln!("Subscribe to capture device events failed: {:#}", e);
+        }
+
+        let this = view.clone();
+        receiver.attach(None, move |event| {
+            this.on_volume_event(event);
+            glib::Continue(true)
+        });
+
         app.connect_activate(move |_app| {
             view.window.show_all();
 
             utils::init_tray(view.clone());
             utils::init(view.clone());
-
-            let view = view.clone();
-            gtk::timeout_add(200, move || {
-                if view.push_view.is_enable() {
-                    let codes = view.push_view.get_key_codes();
-
-                    if utils::is_key_release(&codes) {
-                        view.mute();
-                    }
-                }
-
-                Continue(true)
-            });
+            view.register_hotkeys();
+            view.watch_config();
+            view.sync_state();
         });
     });
 
-    ui.run(&std::env::args().collect::<Vec<_>>());
+    ui.run(&args);
     Ok(())
 }
@@ -0,0 +1,424 @@
+// 按住说话的状态机, 不依赖具体平台, 输入按下和松开, 输出静音和取消静音.
+// 时间由调用者传入 (毫秒), 回放按键记录时可以得到和实际运行相同的结果
+
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::hotkey::Hotkey;
+
+/// 什么时候算松开了按住说话的快捷键
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReleaseMode {
+    /// 按键或者任意一个修饰键松开
+    AnyKey,
+    /// 只看按键本身, 先松开修饰键不影响
+    MainKey,
+}
+
+impl Default for ReleaseMode {
+    fn default() -> Self {
+        ReleaseMode::AnyKey
+    }
+}
+
+impl FromStr for ReleaseMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "any-key" => Ok(ReleaseMode::AnyKey),
+            "main-key" => Ok(ReleaseMode::MainKey),
+            _ => Err(anyhow::anyhow!("unknown release mode: {}", s)),
+        }
+    }
+}
+
+/// 快捷键对应的物理键码, 修饰键的每一组里按下任意一个即可 (如左右两个 Win 键)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chord {
+    pub key: u32,
+    pub modifiers: Vec<Vec<u32>>,
+}
+
+impl Chord {
+    /// Windows 的虚拟键码, 回放时也使用这套键码
+    pub fn from_vk(hotkey: &Hotkey) -> Option<Chord> {
+        Some(Chord {
+            key: hotkey.key.to_vk()?,
+            modifiers: hotkey.modifiers.to_vk().iter().map(|it| it.to_vec()).collect(),
+        })
+    }
+
+    #[cfg(feature = "evdev")]
+    pub fn from_evdev(hotkey: &Hotkey) -> Option<Chord> {
+        Some(Chord {
+            key: hotkey.key.to_evdev()? as u32,
+            modifiers: hotkey
+                .modifiers
+                .to_evdev()
+                .iter()
+                .map(|it| it.iter().map(|&code| code as u32).collect())
+                .collect(),
+        })
+    }
+
+    fn modifiers_down(&self, is_down: &impl Fn(u32) -> bool) -> bool {
+        self.modifiers
+            .iter()
+            .all(|group| group.iter().any(|&code| is_down(code)))
+    }
+
+    pub fn is_pressed(&self, is_down: impl Fn(u32) -> bool) -> bool {
+        is_down(self.key) && self.modifiers_down(&is_down)
+    }
+
+    pub fn is_released(&self, mode: ReleaseMode, is_down: impl Fn(u32) -> bool) -> bool {
+        match mode {
+            ReleaseMode::AnyKey => !self.is_pressed(is_down),
+            ReleaseMode::MainKey => !is_down(self.key),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Unmute,
+    Mute,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PttState {
+    Idle,
+    Held,
+    /// 已经松开, 到 `deadline` 时还没有再次按下才静音
+    Releasing { deadline: u64 },
+}
+
+/// 按下立即取消静音, 按住时的自动重复忽略, 松开后经过 `debounce` 毫秒再静音,
+/// 这期间再次按下视为抖动, 保持取消静音
+#[derive(Debug, Clone)]
+pub struct PttMachine {
+    state: PttState,
+    debounce: u64,
+}
+
+impl PttMachine {
+    pub fn new(debounce: u64) -> PttMachine {
+        PttMachine {
+            state: PttState::Idle,
+            debounce,
+        }
+    }
+
+    pub fn set_debounce(&mut self, debounce: u64) {
+        self.debounce = debounce;
+    }
+
+    /// 需要在这个时间调用 `poll`
+    pub fn deadline(&self) -> Option<u64> {
+        match self.state {
+            PttState::Releasing { deadline } => Some(deadline),
+            _ => None,
+        }
+    }
+
+    pub fn press(&mut self, _now: u64) -> Option<Action> {
+        match self.state {
+            PttState::Idle => {
+                self.state = PttState::Held;
+                Some(Action::Unmute)
+            }
+            PttState::Held | PttState::Releasing { .. } => {
+                self.state = PttState::Held;
+                None
+            }
+        }
+    }
+
+    pub fn release(&mut self, now: u64) -> Option<Action> {
+        if self.state != PttState::Held {
+            return None;
+        }
+
+        if self.debounce == 0 {
+            self.state = PttState::Idle;
+            return Some(Action::Mute);
+        }
+
+        self.state = PttState::Releasing {
+            deadline: now + self.debounce,
+        };
+        None
+    }
+
+    pub fn poll(&mut self, now: u64) -> Option<Action> {
+        match self.state {
+            PttState::Releasing { deadline } if now >= deadline => {
+                self.state = PttState::Idle;
+                Some(Action::Mute)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyEvent {
+    Down(u32),
+    Up(u32),
+    /// 按住不放时系统产生的重复按下
+    Repeat(u32),
+}
+
+/// 按键记录中的一行, 格式为 `<毫秒> down|up|repeat <键码>`, 键码可以写成十六进制 `0x5b`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEvent {
+    pub at: u64,
+    pub event: KeyEvent,
+}
+
+impl FromStr for TraceEvent {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        let (at, kind, code) = match parts.as_slice() {
+            [at, kind, code] => (at, kind, code),
+            _ => return Err(anyhow::anyhow!("expect `<ms> down|up|repeat <code>`: {}", s)),
+        };
+
+        let at = at.parse().with_context(|| format!("invalid time: {}", at))?;
+        let code = match code.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => code.parse(),
+        }
+        .with_context(|| format!("invalid key code: {}", code))?;
+        let event = match *kind {
+            "down" => KeyEvent::Down(code),
+            "up" => KeyEvent::Up(code),
+            "repeat" => KeyEvent::Repeat(code),
+            _ => return Err(anyhow::anyhow!("unknown key event: {}", kind)),
+        };
+
+        Ok(TraceEvent { at, event })
+    }
+}
+
+/// 从原始按键判断快捷键的按下和松开, 再交给状态机
+pub struct KeyTracker {
+    chord: Chord,
+    mode: ReleaseMode,
+    down: HashSet<u32>,
+    active: bool,
+    machine: PttMachine,
+}
+
+impl KeyTracker {
+    pub fn new(chord: Chord, mode: ReleaseMode, debounce: u64) -> KeyTracker {
+        KeyTracker {
+            chord,
+            mode,
+            down: HashSet::new(),
+            active: false,
+            machine: PttMachine::new(debounce),
+        }
+    }
+
+    pub fn machine(&self) -> &PttMachine {
+        &self.machine
+    }
+
+    pub fn feed(&mut self, now: u64, event: KeyEvent) -> Option<Action> {
+        match event {
+            KeyEvent::Down(code) => {
+                self.down.insert(code);
+                // 先按下按键再按修饰键不算, 和系统热键的行为一致
+                let down = &self.down;
+                let pressed =
+                    code == self.chord.key && self.chord.is_pressed(|it| down.contains(&it));
+                if !self.active && pressed {
+                    self.active = true;
+                    return self.machine.press(now);
+                }
+            }
+            KeyEvent::Up(code) => {
+                self.down.remove(&code);
+                let down = &self.down;
+                if self.active && self.chord.is_released(self.mode, |it| down.contains(&it)) {
+                    self.active = false;
+                    return self.machine.release(now);
+                }
+            }
+            // 漏掉了按下时补上, 但不会因为重复触发按下
+            KeyEvent::Repeat(code) => {
+                self.down.insert(code);
+            }
+        }
+
+        None
+    }
+
+    pub fn poll(&mut self, now: u64) -> Option<Action> {
+        self.machine.poll(now)
+    }
+}
+
+/// 回放按键记录, 返回每个动作和发生的时间, 等待中的静音在下一个事件之前或者记录结束时补上
+pub fn replay(
+    chord: Chord,
+    mode: ReleaseMode,
+    debounce: u64,
+    events: &[TraceEvent],
+) -> Vec<(u64, Action)> {
+    let mut tracker = KeyTracker::new(chord, mode, debounce);
+    let mut actions = Vec::new();
+
+    for event in events {
+        if let Some(deadline) = tracker.machine().deadline().filter(|&it| it <= event.at) {
+            actions.extend(tracker.poll(deadline).map(|it| (deadline, it)));
+        }
+        actions.extend(tracker.feed(event.at, event.event).map(|it| (event.at, it)));
+    }
+
+    if let Some(deadline) = tracker.machine().deadline() {
+        actions.extend(tracker.poll(deadline).map(|it| (deadline, it)));
+    }
+
+    actions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_F: u32 = 0x46;
+    const LWIN: u32 = 0x5b;
+    const RWIN: u32 = 0x5c;
+
+    /// Win+F, 左右两个 Win 键都可以
+    fn chord() -> Chord {
+        Chord {
+            key: KEY_F,
+            modifiers: vec![vec![LWIN, RWIN]],
+        }
+    }
+
+    fn trace(lines: &str) -> Vec<TraceEvent> {
+        lines.lines().map(|it| it.parse().unwrap()).collect()
+    }
+
+    fn run(mode: ReleaseMode, debounce: u64, lines: &str) -> Vec<(u64, Action)> {
+        replay(chord(), mode, debounce, &trace(lines))
+    }
+
+    #[test]
+    fn parse_trace_event() {
+        let event: TraceEvent = "120 down 0x5b".parse().unwrap();
+        assert_eq!(event, TraceEvent { at: 120, event: KeyEvent::Down(LWIN) });
+        let event: TraceEvent = "7 repeat 70".parse().unwrap();
+        assert_eq!(event, TraceEvent { at: 7, event: KeyEvent::Repeat(KEY_F) });
+
+        assert!("120 down".parse::<TraceEvent>().is_err());
+        assert!("120 press 70".parse::<TraceEvent>().is_err());
+        assert!("x down 70".parse::<TraceEvent>().is_err());
+    }
+
+    #[test]
+    fn modifier_release_any_key() {
+        let actions = run(
+            ReleaseMode::AnyKey,
+            0,
+            "0 down 0x5b\n10 down 0x46\n50 up 0x5b\n90 up 0x46",
+        );
+        assert_eq!(actions, vec![(10, Action::Unmute), (50, Action::Mute)]);
+    }
+
+    #[test]
+    fn modifier_release_main_key() {
+        let actions = run(
+            ReleaseMode::MainKey,
+            0,
+            "0 down 0x5b\n10 down 0x46\n50 up 0x5b\n90 up 0x46",
+        );
+        assert_eq!(actions, vec![(10, Action::Unmute), (90, Action::Mute)]);
+    }
+
+    #[test]
+    fn other_win_key_still_held() {
+        // 按住左 Win, 中途按下又松开右 Win, 修饰键仍然满足
+        let lines = "0 down 0x5b\n5 down 0x5c\n10 down 0x46\n50 up 0x5c\n90 up 0x46";
+        for &mode in &[ReleaseMode::AnyKey, ReleaseMode::MainKey] {
+            let actions = run(mode, 0, lines);
+            assert_eq!(actions, vec![(10, Action::Unmute), (90, Action::Mute)]);
+        }
+    }
+
+    #[test]
+    fn key_before_modifier() {
+        let actions = run(ReleaseMode::AnyKey, 0, "0 down 0x46\n10 down 0x5b\n50 up 0x46");
+        assert!(actions.is_empty());
+    }
+
+    #[test]
+    fn debounce_inside_window() {
+        // 松开后 20 毫秒再次按下, 还在 30 毫秒的防抖时间内, 保持取消静音
+        let actions = run(
+            ReleaseMode::AnyKey,
+            30,
+            "0 down 0x5b\n10 down 0x46\n100 up 0x46\n120 down 0x46\n200 up 0x46",
+        );
+        assert_eq!(actions, vec![(10, Action::Unmute), (230, Action::Mute)]);
+    }
+
+    #[test]
+    fn debounce_outside_window() {
+        let actions = run(
+            ReleaseMode::AnyKey,
+            30,
+            "0 down 0x5b\n10 down 0x46\n100 up 0x46\n140 down 0x46\n200 up 0x46",
+        );
+        assert_eq!(
+            actions,
+            vec![
+                (10, Action::Unmute),
+                (130, Action::Mute),
+                (140, Action::Unmute),
+                (230, Action::Mute),
+            ]
+        );
+    }
+
+    #[test]
+    fn repeat_does_not_retrigger() {
+        let actions = run(
+            ReleaseMode::AnyKey,
+            0,
+            "0 down 0x5b\n10 down 0x46\n40 repeat 0x46\n70 repeat 0x46\n80 down 0x46\n90 up 0x46",
+        );
+        assert_eq!(actions, vec![(10, Action::Unmute), (90, Action::Mute)]);
+    }
+
+    #[test]
+    fn repeat_does_not_press() {
+        // 漏掉的按下由重复补上, 但只有真正的按下才触发
+        let actions = run(ReleaseMode::AnyKey, 0, "0 repeat 0x5b\n10 repeat 0x46\n20 up 0x46");
+        assert!(actions.is_empty());
+    }
+
+    #[test]
+    fn machine_debounce() {
+        let mut machine = PttMachine::new(30);
+        assert_eq!(machine.press(0), Some(Action::Unmute));
+        assert_eq!(machine.press(5), None);
+        assert_eq!(machine.release(10), None);
+        assert_eq!(machine.deadline(), Some(40));
+        assert_eq!(machine.poll(39), None);
+        assert_eq!(machine.poll(40), Some(Action::Mute));
+        assert_eq!(machine.deadline(), None);
+        assert_eq!(machine.release(50), None);
+    }
+}
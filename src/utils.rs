@@ -1,11 +1,33 @@
 use std::rc::Rc;
 
+use crate::ptt::ReleaseMode;
 use crate::view::View;
 
 pub static mut MAIN_VIEW: Option<Rc<View>> = None;
 
+/// 能拿到原始按键的热键实现按这个判断按住说话是否松开, 随配置方案更新
+pub static mut RELEASE_MODE: ReleaseMode = ReleaseMode::AnyKey;
+
 #[cfg(target_os = "windows")]
 mod win32;
 
 #[cfg(target_os = "windows")]
-pub use win32::*;
\ No newline at end of file
+pub use win32::*;
+
+#[cfg(all(target_os = "linux", any(feature = "x11", feature = "evdev", feature = "portal")))]
+mod linux;
+
+#[cfg(all(target_os = "linux", any(feature = "x11", feature = "evdev", feature = "portal")))]
+pub use linux::*;
+
+#[cfg(not(any(
+    target_os = "windows",
+    all(target_os = "linux", any(feature = "x11", feature = "evdev", feature = "portal"))
+)))]
+mod fallback;
+
+#[cfg(not(any(
+    target_os = "windows",
+    all(target_os = "linux", any(feature = "x11", feature = "evdev", feature = "portal"))
+)))]
+pub use fallback::*;
@@ -0,0 +1,25 @@
+// 其他平台 (以及没有启用任何热键特性的 Linux) 暂时没有全局热键和托盘实现, 这里只提供空实现以保证能够编译运行
+
+use std::rc::Rc;
+
+use crate::hotkey::Hotkey;
+use crate::view::View;
+
+pub fn register_hotkey(_hotkey: &Hotkey, _id: i32) -> bool {
+    false
+}
+
+pub fn unregister_hotkey(_id: i32) -> bool {
+    false
+}
+
+/// 没有全局热键时无法判断是否被占用
+pub fn can_register(_hotkey: &Hotkey) -> bool {
+    true
+}
+
+pub fn init_tray(_view: Rc<View>) {}
+
+pub fn update_tray(_mute: bool) {}
+
+pub fn init(_view: Rc<View>) {}
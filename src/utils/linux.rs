@@ -0,0 +1,239 @@
+// Linux 上的全局热键有多种实现, 通过 `MUTE_HOTKEY_BACKEND` 环境变量选择, 托盘暂时没有实现
+
+use std::rc::Rc;
+use std::str::FromStr;
+
+use anyhow::Result;
+
+use crate::hotkey::Hotkey;
+use crate::view::*;
+
+use super::MAIN_VIEW;
+
+#[cfg(feature = "evdev")]
+mod evdev;
+#[cfg(feature = "portal")]
+mod portal;
+#[cfg(feature = "x11")]
+mod x11;
+
+#[cfg(feature = "evdev")]
+use self::evdev::EvdevHotkeys;
+#[cfg(feature = "portal")]
+use self::portal::PortalHotkeys;
+#[cfg(feature = "x11")]
+use self::x11::X11Hotkeys;
+
+/// 全局热键的一种实现, 按下时调用 `on_hotkey`, 按键或者任意一个修饰键松开时调用 `on_hotkey_release`
+pub trait HotkeyBackend {
+    fn register(&self, hotkey: &Hotkey, id: i32) -> bool;
+
+    fn unregister(&self, id: i32) -> bool;
+
+    /// 是否没有被其他程序占用, 无法判断时返回 true
+    fn can_register(&self, _hotkey: &Hotkey) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotkeyBackendKind {
+    #[cfg(feature = "x11")]
+    X11,
+    #[cfg(feature = "evdev")]
+    Evdev,
+    #[cfg(feature = "portal")]
+    Portal,
+}
+
+impl HotkeyBackendKind {
+    /// Wayland 和 Flatpak 沙盒里使用 portal, 有 X 服务器时使用 X11, 否则直接读取输入设备
+    pub fn platform_default() -> Option<HotkeyBackendKind> {
+        #[cfg(feature = "portal")]
+        {
+            let sandboxed = std::path::Path::new("/.flatpak-info").exists();
+            if sandboxed || std::env::var_os("WAYLAND_DISPLAY").is_some() {
+                return Some(HotkeyBackendKind::Portal);
+            }
+        }
+
+        #[cfg(feature = "x11")]
+        {
+            if std::env::var_os("DISPLAY").is_some() {
+                return Some(HotkeyBackendKind::X11);
+            }
+        }
+
+        #[cfg(feature = "evdev")]
+        return Some(HotkeyBackendKind::Evdev);
+
+        #[allow(unreachable_code)]
+        None
+    }
+}
+
+impl FromStr for HotkeyBackendKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            #[cfg(feature = "x11")]
+            "x11" => Ok(HotkeyBackendKind::X11),
+            #[cfg(feature = "evdev")]
+            "evdev" => Ok(HotkeyBackendKind::Evdev),
+            #[cfg(feature = "portal")]
+            "portal" => Ok(HotkeyBackendKind::Portal),
+            _ => Err(anyhow::anyhow!("unknown hotkey backend: {}", s)),
+        }
+    }
+}
+
+fn open_backend(kind: HotkeyBackendKind) -> Result<Rc<dyn HotkeyBackend>> {
+    match kind {
+        #[cfg(feature = "x11")]
+        HotkeyBackendKind::X11 => Ok(X11Hotkeys::open()?),
+        #[cfg(feature = "evdev")]
+        HotkeyBackendKind::Evdev => Ok(EvdevHotkeys::open()?),
+        #[cfg(feature = "portal")]
+        HotkeyBackendKind::Portal => Ok(PortalHotkeys::open()?),
+    }
+}
+
+static mut BACKEND: Option<Rc<dyn HotkeyBackend>> = None;
+
+fn backend() -> Option<&'static Rc<dyn HotkeyBackend>> {
+    unsafe { BACKEND.as_ref() }
+}
+
+/// 各个实现收到热键后调用
+fn on_hotkey(id: i32) {
+    #[cfg(all(test, any(feature = "x11", feature = "evdev")))]
+    tests::record(id, true);
+
+    unsafe {
+        MAIN_VIEW.as_ref().map(|view| view.on_hotkey(id));
+    }
+}
+
+fn on_hotkey_release(id: i32) {
+    #[cfg(all(test, any(feature = "x11", feature = "evdev")))]
+    tests::record(id, false);
+
+    unsafe {
+        MAIN_VIEW.as_ref().map(|view| view.on_hotkey_release(id));
+    }
+}
+
+/// 注册时无法立即知道结果的实现, 之后绑定失败时调用
+#[cfg(feature = "portal")]
+fn on_hotkey_failed(id: i32, reason: &str) {
+    unsafe {
+        MAIN_VIEW.as_ref().map(|view| view.on_hotkey_failed(id, reason));
+    }
+}
+
+pub fn register_hotkey(hotkey: &Hotkey, id: i32) -> bool {
+    backend().map_or(false, |it| it.register(hotkey, id))
+}
+
+pub fn unregister_hotkey(id: i32) -> bool {
+    backend().map_or(false, |it| it.unregister(id))
+}
+
+pub fn can_register(hotkey: &Hotkey) -> bool {
+    backend().map_or(true, |it| it.can_register(hotkey))
+}
+
+pub fn init_tray(_view: Rc<View>) {}
+
+pub fn update_tray(_mute: bool) {}
+
+pub fn init(_view: Rc<View>) {
+    let kind = match std::env::var("MUTE_HOTKEY_BACKEND") {
+        Ok(name) => name.parse::<HotkeyBackendKind>().map_err(|e| eprintln!("{:#}", e)).ok(),
+        Err(_) => HotkeyBackendKind::platform_default(),
+    };
+
+    let kind = match kind {
+        Some(kind) => kind,
+        None => {
+            eprintln!("No global hotkey backend available");
+            return;
+        }
+    };
+
+    match open_backend(kind) {
+        Ok(backend) => unsafe { BACKEND = Some(backend) },
+        Err(e) => eprintln!("Open {:?} hotkey backend failed: {:#}", kind, e),
+    }
+}
+
+/// X11 和 evdev 的测试共用, 记录触发的热键而不是交给界面处理
+#[cfg(all(test, any(feature = "x11", feature = "evdev")))]
+mod tests {
+    use std::cell::RefCell;
+    use std::time::{Duration, Instant};
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+    const SETTLE: Duration = Duration::from_millis(100);
+
+    thread_local! {
+        // 热键 id 和是否为按下
+        static FIRED: RefCell<Vec<(i32, bool)>> = RefCell::new(Vec::new());
+    }
+
+    pub fn record(id: i32, press: bool) {
+        FIRED.with(|it| it.borrow_mut().push((id, press)));
+    }
+
+    pub fn take_fired() -> Vec<(i32, bool)> {
+        FIRED.with(|it| it.replace(Vec::new()))
+    }
+
+    /// 运行默认主循环直到 `done` 返回 true, 超时返回 false
+    pub fn run_until(done: impl Fn() -> bool) -> bool {
+        let context = glib::MainContext::default();
+        let deadline = Instant::now() + TIMEOUT;
+
+        loop {
+            while context.iteration(false) {}
+
+            if done() {
+                return true;
+            }
+            if Instant::now() > deadline {
+                return false;
+            }
+
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// 等到触发了 `count` 次或者超时, 之后再等一会, 确认没有多余的事件
+    pub fn wait_fired(count: usize) -> Vec<(i32, bool)> {
+        run_until(|| FIRED.with(|it| it.borrow().len()) >= count);
+
+        let settled = Instant::now() + SETTLE;
+        run_until(|| Instant::now() > settled);
+
+        take_fired()
+    }
+
+    /// 测试线程持有默认主循环才能添加 fd 回调, 结束时释放给下一个测试
+    pub struct MainContextGuard(glib::MainContext);
+
+    impl MainContextGuard {
+        pub fn acquire() -> Self {
+            let context = glib::MainContext::default();
+            assert!(context.acquire(), "default main context owned by another thread");
+
+            MainContextGuard(context)
+        }
+    }
+
+    impl Drop for MainContextGuard {
+        fn drop(&mut self) {
+            self.0.release();
+        }
+    }
+}
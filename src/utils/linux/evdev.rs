@@ -0,0 +1,381 @@
+// 直接读取 /dev/input/event* 的按键事件, 不依赖显示服务器, Wayland 和控制台下都可以使用.
+// 只读取不独占设备, 按键同时也会送到当前窗口, 需要当前用户能读取这些设备 (通常是 input 组)
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use ::evdev::{Device, EventType, InputEvent};
+use anyhow::{Context, Result};
+use gio::prelude::*;
+use gio::{FileMonitor, FileMonitorEvent, FileMonitorFlags};
+
+use crate::hotkey::{Hotkey, Modifiers};
+use crate::ptt::Chord;
+use crate::utils::RELEASE_MODE;
+
+use super::{on_hotkey, on_hotkey_release, HotkeyBackend};
+
+const INPUT_DIR: &str = "/dev/input";
+
+pub struct EvdevHotkeys {
+    hotkeys: RefCell<HashMap<i32, Hotkey>>,
+    // 正在按住的热键, 松开时通知
+    pressed: RefCell<HashSet<i32>>,
+
+    // 已经打开的设备和各自当前按下的键码, 拔掉一个键盘不影响另一个键盘上按住的键
+    devices: RefCell<HashMap<PathBuf, HashSet<u16>>>,
+    // 监视 /dev/input, 插入键盘后打开新设备
+    monitor: RefCell<Option<FileMonitor>>,
+}
+
+impl EvdevHotkeys {
+    pub fn open() -> Result<Rc<EvdevHotkeys>> {
+        let hotkeys = Rc::new(EvdevHotkeys {
+            hotkeys: RefCell::new(HashMap::new()),
+            pressed: RefCell::new(HashSet::new()),
+            devices: RefCell::new(HashMap::new()),
+            monitor: RefCell::new(None),
+        });
+
+        for entry in std::fs::read_dir(INPUT_DIR).context("List input devices failed")? {
+            let path = entry?.path();
+            if is_event_device(&path) {
+                // 没有权限的设备直接跳过
+                let _ = hotkeys.add_device(&path);
+            }
+        }
+
+        if hotkeys.devices.borrow().is_empty() {
+            eprintln!("No readable keyboard in {}, check the permission of the devices", INPUT_DIR);
+        }
+
+        hotkeys.watch()?;
+
+        Ok(hotkeys)
+    }
+
+    /// 新设备出现时 udev 可能还没有设置好权限, 权限变化时再试一次
+    fn watch(self: &Rc<Self>) -> Result<()> {
+        let monitor = gio::File::new_for_path(INPUT_DIR)
+            .monitor_directory(FileMonitorFlags::NONE, None::<&gio::Cancellable>)
+            .context("Watch input devices failed")?;
+
+        let this = self.clone();
+        monitor.connect_changed(move |_monitor, file, _other, event| {
+            match event {
+                FileMonitorEvent::Created | FileMonitorEvent::AttributeChanged => {}
+                _ => return,
+            }
+
+            if let Some(path) = file.get_path().filter(|it| is_event_device(it)) {
+                let _ = this.add_device(&path);
+            }
+        });
+
+        *self.monitor.borrow_mut() = Some(monitor);
+
+        Ok(())
+    }
+
+    fn add_device(self: &Rc<Self>, path: &Path) -> Result<()> {
+        if self.devices.borrow().contains_key(path) {
+            return Ok(());
+        }
+
+        let mut device =
+            Device::open(path).with_context(|| format!("Open {} failed", path.display()))?;
+
+        // 没有按键的设备 (如加速度计) 不需要读取
+        let has_keys = device.supported_keys().map_or(false, |keys| keys.iter().next().is_some());
+        if !has_keys {
+            return Ok(());
+        }
+
+        // 可读时 fetch_events 也可能读取不止一次, 阻塞的读取会卡住界面
+        let fd = device.as_raw_fd();
+        let nonblocking = unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            flags >= 0 && libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) >= 0
+        };
+        if !nonblocking {
+            return Err(io::Error::last_os_error())
+                .with_context(|| format!("Set {} non-blocking failed", path.display()));
+        }
+
+        let this = self.clone();
+        let device_path = path.to_path_buf();
+        glib::source::unix_fd_add_local(fd, glib::IOCondition::IN, move |_fd, _condition| {
+            let events: Vec<InputEvent> = match device.fetch_events() {
+                Ok(events) => events.collect(),
+                // 暂时没有可读的事件
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return glib::Continue(true),
+                Err(_) => {
+                    // 设备被拔掉
+                    this.remove_device(&device_path);
+                    return glib::Continue(false);
+                }
+            };
+
+            for event in events {
+                if event.event_type() == EventType::KEY {
+                    this.on_key(&device_path, event.code(), event.value());
+                }
+            }
+
+            glib::Continue(true)
+        });
+
+        self.devices.borrow_mut().insert(path.to_path_buf(), HashSet::new());
+
+        Ok(())
+    }
+
+    /// 拔掉时按住的键收不到松开的事件, 当作这个设备上的键全部松开
+    fn remove_device(&self, path: &Path) {
+        self.devices.borrow_mut().remove(path);
+        self.release();
+    }
+
+    /// 任意一个设备上按下了这个键
+    fn is_down(&self, code: u16) -> bool {
+        self.devices.borrow().values().any(|down| down.contains(&code))
+    }
+
+    /// `value` 为 1 表示按下, 0 表示松开, 2 是按住不放时的自动重复
+    fn on_key(&self, path: &Path, code: u16, value: i32) {
+        let press = match value {
+            0 => false,
+            1 => true,
+            _ => return,
+        };
+
+        if let Some(down) = self.devices.borrow_mut().get_mut(path) {
+            if press {
+                down.insert(code);
+            } else {
+                down.remove(&code);
+            }
+        }
+
+        if !press {
+            self.release();
+            return;
+        }
+
+        let modifiers = self.modifiers();
+        let ids: Vec<i32> = self
+            .hotkeys
+            .borrow()
+            .iter()
+            .filter(|(_, hotkey)| {
+                hotkey.key.to_evdev() == Some(code) && hotkey.modifiers == modifiers
+            })
+            .map(|(&id, _)| id)
+            .collect();
+
+        for id in ids {
+            if self.pressed.borrow_mut().insert(id) {
+                on_hotkey(id);
+            }
+        }
+    }
+
+    /// 按 `RELEASE_MODE` 判断按住的热键是否松开
+    fn release(&self) {
+        let mode = unsafe { RELEASE_MODE };
+        let released: Vec<i32> = self
+            .pressed
+            .borrow()
+            .iter()
+            .filter(|id| match self.hotkeys.borrow().get(id).and_then(Chord::from_evdev) {
+                Some(chord) => chord.is_released(mode, |code| self.is_down(code as u16)),
+                None => true,
+            })
+            .copied()
+            .collect();
+
+        for id in released {
+            self.pressed.borrow_mut().remove(&id);
+            on_hotkey_release(id);
+        }
+    }
+
+    /// 左右两侧的修饰键按下其中一个即可
+    fn is_held(&self, modifiers: Modifiers) -> bool {
+        modifiers
+            .to_evdev()
+            .iter()
+            .all(|codes| codes.iter().any(|&code| self.is_down(code)))
+    }
+
+    /// 当前按下的修饰键
+    fn modifiers(&self) -> Modifiers {
+        let only = Modifiers::default();
+
+        Modifiers {
+            ctrl: self.is_held(Modifiers { ctrl: true, ..only }),
+            alt: self.is_held(Modifiers { alt: true, ..only }),
+            shift: self.is_held(Modifiers { shift: true, ..only }),
+            win: self.is_held(Modifiers { win: true, ..only }),
+        }
+    }
+}
+
+fn is_event_device(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|it| it.to_str())
+        .map_or(false, |it| it.starts_with("event"))
+}
+
+impl HotkeyBackend for EvdevHotkeys {
+    fn register(&self, hotkey: &Hotkey, id: i32) -> bool {
+        if hotkey.key.to_evdev().is_none() {
+            return false;
+        }
+
+        self.hotkeys.borrow_mut().insert(id, *hotkey);
+        true
+    }
+
+    fn unregister(&self, id: i32) -> bool {
+        self.hotkeys.borrow_mut().remove(&id).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::evdev::uinput::VirtualDeviceBuilder;
+    use ::evdev::{AttributeSet, Key};
+
+    use super::*;
+    use crate::utils::linux::tests::{run_until, take_fired, wait_fired, MainContextGuard};
+
+    const LEFT_CTRL: u16 = 29;
+    const RIGHT_CTRL: u16 = 97;
+    const LEFT_SHIFT: u16 = 42;
+    const M: u16 = 50;
+
+    /// 不打开真实设备, 直接把按键交给 `on_key`
+    fn new_hotkeys(devices: &[&str]) -> EvdevHotkeys {
+        let hotkeys = EvdevHotkeys {
+            hotkeys: RefCell::new(HashMap::new()),
+            pressed: RefCell::new(HashSet::new()),
+            devices: RefCell::new(
+                devices
+                    .iter()
+                    .map(|&path| (PathBuf::from(path), HashSet::new()))
+                    .collect(),
+            ),
+            monitor: RefCell::new(None),
+        };
+        assert!(hotkeys.register(&"Ctrl+M".parse().unwrap(), 1));
+
+        hotkeys
+    }
+
+    fn keys(hotkeys: &EvdevHotkeys, device: &str, events: &[(u16, i32)]) {
+        for &(code, value) in events {
+            hotkeys.on_key(Path::new(device), code, value);
+        }
+    }
+
+    #[test]
+    fn press_and_release() {
+        let hotkeys = new_hotkeys(&["event0"]);
+
+        keys(&hotkeys, "event0", &[(LEFT_CTRL, 1), (M, 1), (M, 2), (M, 2)]);
+        assert_eq!(take_fired(), vec![(1, true)]);
+
+        keys(&hotkeys, "event0", &[(M, 0), (LEFT_CTRL, 0)]);
+        assert_eq!(take_fired(), vec![(1, false)]);
+    }
+
+    #[test]
+    fn either_side_and_exact_modifiers() {
+        let hotkeys = new_hotkeys(&["event0"]);
+
+        keys(&hotkeys, "event0", &[(RIGHT_CTRL, 1), (M, 1), (M, 0), (RIGHT_CTRL, 0)]);
+        assert_eq!(take_fired(), vec![(1, true), (1, false)]);
+
+        // 多按了 Shift 不是同一个热键
+        keys(&hotkeys, "event0", &[(LEFT_CTRL, 1), (LEFT_SHIFT, 1), (M, 1)]);
+        assert_eq!(take_fired(), vec![]);
+    }
+
+    #[test]
+    fn keys_tracked_per_device() {
+        let hotkeys = new_hotkeys(&["event0", "event1"]);
+
+        // 修饰键和按键可以在不同的键盘上
+        keys(&hotkeys, "event0", &[(LEFT_CTRL, 1)]);
+        keys(&hotkeys, "event1", &[(M, 1)]);
+        assert_eq!(take_fired(), vec![(1, true)]);
+
+        // 另一个键盘上还按着同一个键, 不算松开
+        keys(&hotkeys, "event0", &[(M, 1), (M, 0)]);
+        assert_eq!(take_fired(), vec![]);
+
+        keys(&hotkeys, "event1", &[(M, 0)]);
+        assert_eq!(take_fired(), vec![(1, false)]);
+    }
+
+    #[test]
+    fn unplug_releases_keys() {
+        let hotkeys = new_hotkeys(&["event0", "event1"]);
+
+        keys(&hotkeys, "event0", &[(LEFT_CTRL, 1), (M, 1)]);
+        assert_eq!(take_fired(), vec![(1, true)]);
+
+        hotkeys.remove_device(Path::new("event0"));
+        assert_eq!(take_fired(), vec![(1, false)]);
+        assert!(!hotkeys.is_down(LEFT_CTRL));
+    }
+
+    /// 需要能写入 /dev/uinput 并读取新出现的输入设备, 见 README
+    #[test]
+    #[ignore]
+    fn uinput_keyboard() {
+        let _context = MainContextGuard::acquire();
+        let hotkeys = EvdevHotkeys::open().unwrap();
+        assert!(hotkeys.register(&"Ctrl+M".parse().unwrap(), 1));
+        let opened = hotkeys.devices.borrow().len();
+
+        let mut supported = AttributeSet::<Key>::new();
+        supported.insert(Key::KEY_LEFTCTRL);
+        supported.insert(Key::KEY_M);
+        let mut keyboard = VirtualDeviceBuilder::new()
+            .unwrap()
+            .name("mute test keyboard")
+            .with_keys(&supported)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        // 插入的键盘通过目录监视打开
+        assert!(run_until(|| hotkeys.devices.borrow().len() > opened));
+
+        let mut send = |events: &[(u16, i32)]| {
+            let events: Vec<InputEvent> = events
+                .iter()
+                .map(|&(code, value)| InputEvent::new(EventType::KEY, code, value))
+                .collect();
+            keyboard.emit(&events).unwrap();
+        };
+
+        send(&[(LEFT_CTRL, 1), (M, 1)]);
+        assert_eq!(wait_fired(1), vec![(1, true)]);
+        send(&[(M, 0), (LEFT_CTRL, 0)]);
+        assert_eq!(wait_fired(1), vec![(1, false)]);
+
+        // 按住时拔掉键盘当作松开
+        send(&[(LEFT_CTRL, 1), (M, 1)]);
+        assert_eq!(wait_fired(1), vec![(1, true)]);
+        drop(keyboard);
+        assert_eq!(wait_fired(1), vec![(1, false)]);
+        assert_eq!(hotkeys.devices.borrow().len(), opened);
+    }
+}
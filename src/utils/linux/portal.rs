@@ -0,0 +1,677 @@
+// 通过 org.freedesktop.portal.GlobalShortcuts 注册快捷键, 用于沙盒和 Wayland 会话.
+// 实际触发的按键由桌面环境决定, 这里提供的只是建议的组合
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use zbus::{Connection, Message, MessageType};
+use zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value};
+
+use crate::hotkey::Hotkey;
+use crate::view::{
+    COUGH_HOTKEY, GAIN_DOWN_HOTKEY, GAIN_UP_HOTKEY, MUTE_HOTKEY, PROFILE_HOTKEY, PUSH_HOTKEY,
+};
+
+use super::{on_hotkey, on_hotkey_failed, on_hotkey_release, HotkeyBackend};
+
+const PORTAL_NAME: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+const SHORTCUTS_INTERFACE: &str = "org.freedesktop.portal.GlobalShortcuts";
+const REQUEST_INTERFACE: &str = "org.freedesktop.portal.Request";
+const SESSION_INTERFACE: &str = "org.freedesktop.portal.Session";
+
+/// 热键 id 对应的 portal 快捷键 id 和描述
+const SHORTCUTS: &[(i32, &str, &str)] = &[
+    (MUTE_HOTKEY, "toggle-mute", "切换麦克风静音"),
+    (PUSH_HOTKEY, "push-to-talk", "按住说话"),
+    (GAIN_UP_HOTKEY, "gain-up", "增大输入音量"),
+    (GAIN_DOWN_HOTKEY, "gain-down", "减小输入音量"),
+    (PROFILE_HOTKEY, "next-profile", "切换到下一个配置方案"),
+    (COUGH_HOTKEY, "push-to-mute", "按住静音"),
+];
+
+fn shortcut_id(id: i32) -> Option<(&'static str, &'static str)> {
+    SHORTCUTS
+        .iter()
+        .find(|it| it.0 == id)
+        .map(|&(_, name, description)| (name, description))
+}
+
+fn hotkey_id(name: &str) -> Option<i32> {
+    SHORTCUTS.iter().find(|it| it.1 == name).map(|it| it.0)
+}
+
+/// XDG shortcuts 规范的写法, 如 `CTRL+ALT+m`, 按键为 xkb 的 keysym 名字
+fn trigger(hotkey: &Hotkey) -> Option<String> {
+    let keysym = hotkey.key.to_keysym()?;
+    let name = gdk::keyval_name(keysym)?;
+
+    let modifiers = [
+        (hotkey.modifiers.ctrl, "CTRL"),
+        (hotkey.modifiers.alt, "ALT"),
+        (hotkey.modifiers.shift, "SHIFT"),
+        (hotkey.modifiers.win, "LOGO"),
+    ];
+    let mut parts: Vec<&str> = modifiers
+        .iter()
+        .filter(|it| it.0)
+        .map(|it| it.1)
+        .collect();
+    parts.push(name.as_str());
+
+    Some(parts.join("+"))
+}
+
+/// 后台线程发回主线程的事件, `usize` 为第几次绑定
+enum PortalEvent {
+    /// 快捷键有变化, 多次注册合并成一次绑定
+    Rebind,
+    /// 连接到 session bus, 重新绑定时通过它唤醒旧的线程
+    Connected(usize, Connection),
+    /// 没有绑定成功的热键和原因
+    Failed(usize, Vec<i32>, String),
+    Activated(String),
+    Deactivated(String),
+}
+
+pub struct PortalHotkeys {
+    hotkeys: RefCell<HashMap<i32, Hotkey>>,
+    // portal 报告正在按住的热键
+    pressed: RefCell<HashSet<i32>>,
+
+    // 每次重新绑定加一, 旧的线程看到后关闭自己的会话并退出
+    generation: Arc<AtomicUsize>,
+    // 当前线程的连接
+    connection: RefCell<Option<Connection>>,
+    rebind_pending: Cell<bool>,
+
+    sender: glib::Sender<PortalEvent>,
+}
+
+impl PortalHotkeys {
+    pub fn open() -> Result<Rc<PortalHotkeys>> {
+        // 先确认 session bus 可用, 绑定在注册快捷键之后才进行
+        Connection::new_session().context("Connect to session bus failed")?;
+
+        let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+        let hotkeys = Rc::new(PortalHotkeys {
+            hotkeys: RefCell::new(HashMap::new()),
+            pressed: RefCell::new(HashSet::new()),
+            generation: Arc::new(AtomicUsize::new(0)),
+            connection: RefCell::new(None),
+            rebind_pending: Cell::new(false),
+            sender,
+        });
+
+        let this = hotkeys.clone();
+        receiver.attach(None, move |event| {
+            this.on_event(event);
+            glib::Continue(true)
+        });
+
+        Ok(hotkeys)
+    }
+
+    fn is_current(&self, generation: usize) -> bool {
+        generation == self.generation.load(Ordering::SeqCst)
+    }
+
+    fn on_event(&self, event: PortalEvent) {
+        match event {
+            PortalEvent::Rebind => {
+                self.rebind_pending.set(false);
+                self.rebind();
+            }
+            PortalEvent::Connected(generation, connection) => {
+                if self.is_current(generation) {
+                    *self.connection.borrow_mut() = Some(connection);
+                } else {
+                    wake(&connection);
+                }
+            }
+            PortalEvent::Failed(generation, ids, reason) => {
+                if self.is_current(generation) {
+                    for id in ids {
+                        on_hotkey_failed(id, &reason);
+                    }
+                }
+            }
+            PortalEvent::Activated(name) => {
+                if let Some(id) = hotkey_id(&name) {
+                    // 按住不放时只触发一次
+                    if self.pressed.borrow_mut().insert(id) {
+                        on_hotkey(id);
+                    }
+                }
+            }
+            PortalEvent::Deactivated(name) => {
+                if let Some(id) = hotkey_id(&name) {
+                    if self.pressed.borrow_mut().remove(&id) {
+                        on_hotkey_release(id);
+                    }
+                }
+            }
+        }
+    }
+
+    fn schedule_rebind(&self) {
+        if !self.rebind_pending.replace(true) {
+            let _ = self.sender.send(PortalEvent::Rebind);
+        }
+    }
+
+    /// 让旧的线程退出, 在新线程里建立会话并绑定当前所有的快捷键
+    fn rebind(&self) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        if let Some(connection) = self.connection.borrow_mut().take() {
+            wake(&connection);
+        }
+
+        // 旧的会话收不到松开了
+        let pressed: Vec<i32> = self.pressed.borrow_mut().drain().collect();
+        for id in pressed {
+            on_hotkey_release(id);
+        }
+
+        let shortcuts: Vec<(i32, Option<String>)> = self
+            .hotkeys
+            .borrow()
+            .iter()
+            .map(|(&id, hotkey)| (id, trigger(hotkey)))
+            .collect();
+        if shortcuts.is_empty() {
+            return;
+        }
+
+        let current = self.generation.clone();
+        let sender = self.sender.clone();
+        std::thread::spawn(move || {
+            let send = |event: PortalEvent| {
+                let _ = sender.send(event);
+            };
+            if let Err(e) = run_session(generation, &current, &shortcuts, &send) {
+                eprintln!("Bind global shortcuts failed: {:#}", e);
+                let ids = shortcuts.iter().map(|it| it.0).collect();
+                send(PortalEvent::Failed(generation, ids, format!("{:#}", e)));
+            }
+        });
+    }
+}
+
+impl HotkeyBackend for PortalHotkeys {
+    /// 是否绑定成功由桌面环境异步决定, 这里总是返回 true, 失败时通过 `on_hotkey_failed` 通知
+    fn register(&self, hotkey: &Hotkey, id: i32) -> bool {
+        if shortcut_id(id).is_none() {
+            return false;
+        }
+
+        self.hotkeys.borrow_mut().insert(id, *hotkey);
+        self.schedule_rebind();
+        true
+    }
+
+    fn unregister(&self, id: i32) -> bool {
+        let removed = self.hotkeys.borrow_mut().remove(&id).is_some();
+        if removed {
+            self.schedule_rebind();
+        }
+
+        removed
+    }
+}
+
+/// 给连接自己发一条 Ping, 让阻塞在 `receive_message` 的线程返回并检查代数
+fn wake(connection: &Connection) {
+    let name = match connection.unique_name() {
+        Some(name) => name.to_string(),
+        None => return,
+    };
+    let message = Message::method(
+        None,
+        Some(&name),
+        "/",
+        Some("org.freedesktop.DBus.Peer"),
+        "Ping",
+        &(),
+    );
+
+    if let Err(e) = message.and_then(|it| connection.send_message(it)) {
+        eprintln!("Wake global shortcuts thread failed: {:#}", e);
+    }
+}
+
+/// 不等待回复
+fn close_session(connection: &Connection, session: &str) {
+    let message = Message::method(
+        None,
+        Some(PORTAL_NAME),
+        session,
+        Some(SESSION_INTERFACE),
+        "Close",
+        &(),
+    );
+
+    if let Err(e) = message.and_then(|it| connection.send_message(it)) {
+        eprintln!("Close global shortcuts session failed: {:#}", e);
+    }
+}
+
+fn add_match(connection: &Connection, rule: &str) -> Result<()> {
+    connection.call_method(
+        Some("org.freedesktop.DBus"),
+        "/org/freedesktop/DBus",
+        Some("org.freedesktop.DBus"),
+        "AddMatch",
+        &(rule),
+    )?;
+
+    Ok(())
+}
+
+/// portal 的方法只返回一个 Request, 结果通过它的 Response 信号送回.
+/// 等待期间重新绑定时返回 None
+fn wait_response(
+    connection: &Connection,
+    request: &OwnedObjectPath,
+    stale: &dyn Fn() -> bool,
+) -> Result<Option<HashMap<String, OwnedValue>>> {
+    loop {
+        let message = connection.receive_message()?;
+        if stale() {
+            return Ok(None);
+        }
+        if message.message_type() != MessageType::Signal {
+            continue;
+        }
+
+        let header = message.header()?;
+        let is_response = header.interface()? == Some(REQUEST_INTERFACE)
+            && header.member()? == Some("Response")
+            && header.path()?.map(|it| it.as_str()) == Some(request.as_str());
+        if !is_response {
+            continue;
+        }
+
+        let (response, results): (u32, HashMap<String, OwnedValue>) = message.body()?;
+        if response != 0 {
+            return Err(anyhow::anyhow!("request was cancelled or failed ({})", response));
+        }
+
+        return Ok(Some(results));
+    }
+}
+
+/// BindShortcuts 的结果中实际绑定了的快捷键, 结果中没有这一项时返回 None
+fn bound_shortcuts(results: &HashMap<String, OwnedValue>) -> Option<Vec<String>> {
+    let shortcuts = match results.get("shortcuts").map(|it| &**it) {
+        Some(Value::Array(shortcuts)) => shortcuts,
+        _ => return None,
+    };
+
+    let names = shortcuts
+        .get()
+        .iter()
+        .filter_map(|it| match it {
+            Value::Structure(shortcut) => match shortcut.fields().first() {
+                Some(Value::Str(name)) => Some(name.as_str().to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+
+    Some(names)
+}
+
+/// 建立会话后一直读取快捷键的信号, 直到重新绑定, 退出前关闭自己的会话
+fn run_session(
+    generation: usize,
+    current: &AtomicUsize,
+    shortcuts: &[(i32, Option<String>)],
+    send: &dyn Fn(PortalEvent),
+) -> Result<()> {
+    let connection = Connection::new_session()?;
+    send(PortalEvent::Connected(generation, connection.clone()));
+    let stale = || current.load(Ordering::SeqCst) != generation;
+
+    add_match(&connection, "type='signal',interface='org.freedesktop.portal.Request'")?;
+    add_match(&connection, "type='signal',interface='org.freedesktop.portal.GlobalShortcuts'")?;
+
+    let token = format!("mute_assistant_{}", generation);
+    let mut options: HashMap<&str, Value> = HashMap::new();
+    options.insert("handle_token", Value::from(token.as_str()));
+    options.insert("session_handle_token", Value::from(token.as_str()));
+
+    let request: OwnedObjectPath = connection
+        .call_method(
+            Some(PORTAL_NAME),
+            PORTAL_PATH,
+            Some(SHORTCUTS_INTERFACE),
+            "CreateSession",
+            &(options),
+        )?
+        .body()?;
+    let results = match wait_response(&connection, &request, &stale)? {
+        Some(results) => results,
+        None => return Ok(()),
+    };
+    let session = match results.get("session_handle").map(|it| &**it) {
+        Some(Value::Str(it)) => it.as_str().to_string(),
+        Some(Value::ObjectPath(it)) => it.as_str().to_string(),
+        _ => return Err(anyhow::anyhow!("CreateSession returned no session handle")),
+    };
+
+    let result = listen(generation, &connection, &session, shortcuts, &stale, send);
+    close_session(&connection, &session);
+
+    result
+}
+
+fn listen(
+    generation: usize,
+    connection: &Connection,
+    session: &str,
+    shortcuts: &[(i32, Option<String>)],
+    stale: &dyn Fn() -> bool,
+    send: &dyn Fn(PortalEvent),
+) -> Result<()> {
+    let list: Vec<(&str, HashMap<&str, Value>)> = shortcuts
+        .iter()
+        .filter_map(|(id, trigger)| {
+            let (name, description) = shortcut_id(*id)?;
+            let mut properties = HashMap::new();
+            properties.insert("description", Value::from(description));
+            if let Some(trigger) = trigger {
+                properties.insert("preferred_trigger", Value::from(trigger.as_str()));
+            }
+            Some((name, properties))
+        })
+        .collect();
+
+    let token = format!("mute_assistant_bind_{}", generation);
+    let mut options: HashMap<&str, Value> = HashMap::new();
+    options.insert("handle_token", Value::from(token.as_str()));
+
+    let request: OwnedObjectPath = connection
+        .call_method(
+            Some(PORTAL_NAME),
+            PORTAL_PATH,
+            Some(SHORTCUTS_INTERFACE),
+            "BindShortcuts",
+            &(ObjectPath::try_from(session)?, list, "", options),
+        )?
+        .body()?;
+    let results = match wait_response(connection, &request, stale)? {
+        Some(results) => results,
+        None => return Ok(()),
+    };
+
+    // 用户可能在桌面环境的对话框里拒绝了其中一部分
+    if let Some(bound) = bound_shortcuts(&results) {
+        let failed: Vec<i32> = shortcuts
+            .iter()
+            .map(|it| it.0)
+            .filter(|&id| shortcut_id(id).map_or(true, |it| !bound.iter().any(|b| b == it.0)))
+            .collect();
+        if !failed.is_empty() {
+            send(PortalEvent::Failed(generation, failed, "没有被桌面环境绑定".to_string()));
+        }
+    }
+
+    loop {
+        let message = connection.receive_message()?;
+        if stale() {
+            return Ok(());
+        }
+        if message.message_type() != MessageType::Signal {
+            continue;
+        }
+
+        let header = message.header()?;
+        if header.interface()? != Some(SHORTCUTS_INTERFACE) {
+            continue;
+        }
+        let member = header.member()?.map(|it| it.to_string());
+
+        let (handle, name, _timestamp, _options): (
+            OwnedObjectPath,
+            String,
+            u64,
+            HashMap<String, OwnedValue>,
+        ) = match message.body() {
+            Ok(it) => it,
+            Err(_) => continue,
+        };
+        if handle.as_str() != session {
+            continue;
+        }
+
+        let event = match member.as_deref() {
+            Some("Activated") => PortalEvent::Activated(name),
+            Some("Deactivated") => PortalEvent::Deactivated(name),
+            _ => continue,
+        };
+        send(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::time::Duration;
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn shortcut_names_round_trip() {
+        for &(id, name, _) in SHORTCUTS {
+            assert_eq!(shortcut_id(id).map(|it| it.0), Some(name));
+            assert_eq!(hotkey_id(name), Some(id));
+        }
+        assert_eq!(shortcut_id(0), None);
+        assert_eq!(hotkey_id("unknown"), None);
+    }
+
+    /// 模拟的 portal 发生的事
+    #[derive(Debug, PartialEq)]
+    enum MockEvent {
+        Session(String),
+        Bound(String),
+        Closed(String),
+    }
+
+    /// 在 session bus 上冒充 portal, 只绑定 `bound` 中的快捷键
+    fn mock_portal(bound: &'static [&'static str]) -> (Connection, Receiver<MockEvent>) {
+        let connection = Connection::new_session().unwrap();
+        let reply: u32 = connection
+            .call_method(
+                Some("org.freedesktop.DBus"),
+                "/org/freedesktop/DBus",
+                Some("org.freedesktop.DBus"),
+                "RequestName",
+                // 可以替换, 也允许之后的测试替换
+                &(PORTAL_NAME, 7u32),
+            )
+            .unwrap()
+            .body()
+            .unwrap();
+        assert_eq!(reply, 1, "{} is already owned", PORTAL_NAME);
+
+        let (sender, receiver) = channel();
+        let portal = connection.clone();
+        std::thread::spawn(move || loop {
+            let message = match portal.receive_message() {
+                Ok(it) => it,
+                Err(_) => return,
+            };
+            if message.message_type() == MessageType::MethodCall {
+                let _ = serve(&portal, &message, bound, &sender);
+            }
+        });
+
+        (connection, receiver)
+    }
+
+    fn serve(
+        portal: &Connection,
+        message: &Message,
+        bound: &[&str],
+        events: &Sender<MockEvent>,
+    ) -> Result<()> {
+        let header = message.header()?;
+        let sender = header.sender()?.unwrap_or_default().trim_start_matches(':');
+        let sender = sender.replace('.', "_");
+        let path = header.path()?.map(|it| it.as_str().to_string()).unwrap_or_default();
+
+        match header.member()? {
+            Some("CreateSession") => {
+                let options: HashMap<String, OwnedValue> = message.body()?;
+                let token = match options.get("session_handle_token").map(|it| &**it) {
+                    Some(Value::Str(it)) => it.as_str().to_string(),
+                    _ => return Err(anyhow::anyhow!("no session token")),
+                };
+                let request = format!("{}/request/{}/{}", PORTAL_PATH, sender, token);
+                let session = format!("{}/session/{}/{}", PORTAL_PATH, sender, token);
+
+                portal.reply(message, &ObjectPath::try_from(request.as_str())?)?;
+                let mut results: HashMap<&str, Value> = HashMap::new();
+                results.insert("session_handle", Value::from(session.as_str()));
+                let body = (0u32, results);
+                portal.emit_signal(None, &request, REQUEST_INTERFACE, "Response", &body)?;
+                events.send(MockEvent::Session(session)).unwrap();
+            }
+            Some("BindShortcuts") => {
+                type Shortcuts = Vec<(String, HashMap<String, OwnedValue>)>;
+                let (session, shortcuts, _parent, options): (
+                    OwnedObjectPath,
+                    Shortcuts,
+                    String,
+                    HashMap<String, OwnedValue>,
+                ) = message.body()?;
+                let token = match options.get("handle_token").map(|it| &**it) {
+                    Some(Value::Str(it)) => it.as_str().to_string(),
+                    _ => return Err(anyhow::anyhow!("no handle token")),
+                };
+                let request = format!("{}/request/{}/{}", PORTAL_PATH, sender, token);
+
+                portal.reply(message, &ObjectPath::try_from(request.as_str())?)?;
+                let shortcuts: Vec<(&str, HashMap<&str, Value>)> = shortcuts
+                    .iter()
+                    .filter(|it| bound.contains(&it.0.as_str()))
+                    .map(|it| (it.0.as_str(), HashMap::new()))
+                    .collect();
+                let mut results: HashMap<&str, Value> = HashMap::new();
+                results.insert("shortcuts", Value::from(shortcuts));
+                let body = (0u32, results);
+                portal.emit_signal(None, &request, REQUEST_INTERFACE, "Response", &body)?;
+                events.send(MockEvent::Bound(session.as_str().to_string())).unwrap();
+            }
+            Some("Close") => {
+                portal.reply(message, &())?;
+                events.send(MockEvent::Closed(path)).unwrap();
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn emit(portal: &Connection, member: &str, session: &str, name: &str) {
+        let options: HashMap<&str, Value> = HashMap::new();
+        let body = (ObjectPath::try_from(session).unwrap(), name, 0u64, options);
+        portal.emit_signal(None, PORTAL_PATH, SHORTCUTS_INTERFACE, member, &body).unwrap();
+    }
+
+    /// 在后台线程运行 `run_session`, 事件从返回的 channel 读取
+    fn start(
+        current: &Arc<AtomicUsize>,
+        shortcuts: Vec<(i32, Option<String>)>,
+    ) -> (std::thread::JoinHandle<Result<()>>, Receiver<PortalEvent>) {
+        let generation = current.load(Ordering::SeqCst);
+        let current = current.clone();
+        let (sender, receiver) = channel();
+
+        let worker = std::thread::spawn(move || {
+            let send = |event: PortalEvent| {
+                let _ = sender.send(event);
+            };
+            run_session(generation, &current, &shortcuts, &send)
+        });
+
+        (worker, receiver)
+    }
+
+    fn connected(events: &Receiver<PortalEvent>) -> Connection {
+        match events.recv_timeout(TIMEOUT).unwrap() {
+            PortalEvent::Connected(_, connection) => connection,
+            _ => panic!("expect Connected first"),
+        }
+    }
+
+    // 需要一个空的 session bus, 如
+    // dbus-run-session -- cargo test --features portal -- --ignored --test-threads=1 portal
+    #[test]
+    #[ignore]
+    fn portal_signals_and_rebind() {
+        let (portal, mock) = mock_portal(&["toggle-mute", "push-to-talk"]);
+        let current = Arc::new(AtomicUsize::new(1));
+        let shortcuts = vec![(MUTE_HOTKEY, None), (PUSH_HOTKEY, Some("CTRL+space".to_string()))];
+        let (worker, events) = start(&current, shortcuts);
+
+        let connection = connected(&events);
+        let session = match mock.recv_timeout(TIMEOUT).unwrap() {
+            MockEvent::Session(session) => session,
+            other => panic!("unexpected {:?}", other),
+        };
+
+        // 等 BindShortcuts 的 Response 发出, 之后从同一连接发出的信号不会先到
+        assert_eq!(mock.recv_timeout(TIMEOUT).unwrap(), MockEvent::Bound(session.clone()));
+        emit(&portal, "Activated", &session, "push-to-talk");
+        emit(&portal, "Activated", "/other/session", "toggle-mute");
+        emit(&portal, "Deactivated", &session, "push-to-talk");
+
+        match events.recv_timeout(TIMEOUT).unwrap() {
+            PortalEvent::Activated(name) => assert_eq!(name, "push-to-talk"),
+            _ => panic!("expect Activated"),
+        }
+        match events.recv_timeout(TIMEOUT).unwrap() {
+            PortalEvent::Deactivated(name) => assert_eq!(name, "push-to-talk"),
+            _ => panic!("expect Deactivated"),
+        }
+
+        // 重新绑定后旧的线程不等下一个 portal 信号就退出, 并关闭自己的会话
+        current.fetch_add(1, Ordering::SeqCst);
+        wake(&connection);
+        assert_eq!(mock.recv_timeout(TIMEOUT).unwrap(), MockEvent::Closed(session));
+        worker.join().unwrap().unwrap();
+    }
+
+    #[test]
+    #[ignore]
+    fn portal_reports_unbound_shortcuts() {
+        let (_portal, mock) = mock_portal(&["toggle-mute"]);
+        let current = Arc::new(AtomicUsize::new(1));
+        let (worker, events) = start(&current, vec![(MUTE_HOTKEY, None), (PUSH_HOTKEY, None)]);
+
+        let connection = connected(&events);
+        match events.recv_timeout(TIMEOUT).unwrap() {
+            PortalEvent::Failed(generation, ids, _) => {
+                assert_eq!(generation, 1);
+                assert_eq!(ids, vec![PUSH_HOTKEY]);
+            }
+            _ => panic!("expect Failed"),
+        }
+
+        current.fetch_add(1, Ordering::SeqCst);
+        wake(&connection);
+        worker.join().unwrap().unwrap();
+        assert!(matches!(mock.recv_timeout(TIMEOUT).unwrap(), MockEvent::Session(_)));
+    }
+}
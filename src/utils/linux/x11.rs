@@ -0,0 +1,352 @@
+// X11 上通过 XGrabKey 在根窗口上抓取全局热键
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::os::raw::c_int;
+use std::ptr::{null, null_mut};
+use std::rc::Rc;
+
+use ::x11::keysym::{
+    XK_Alt_L, XK_Alt_R, XK_Control_L, XK_Control_R, XK_Hyper_L, XK_Hyper_R, XK_Meta_L, XK_Meta_R,
+    XK_Num_Lock, XK_Shift_L, XK_Shift_R, XK_Super_L, XK_Super_R,
+};
+use ::x11::xlib;
+use anyhow::Result;
+
+use crate::hotkey::Hotkey;
+use crate::ptt::ReleaseMode;
+use crate::utils::RELEASE_MODE;
+
+use super::{on_hotkey, on_hotkey_release, HotkeyBackend};
+
+/// 松开时算作松开修饰键的 keysym, CapsLock 等锁定键不算
+const MODIFIERS: [u32; 12] = [
+    XK_Shift_L,
+    XK_Shift_R,
+    XK_Control_L,
+    XK_Control_R,
+    XK_Alt_L,
+    XK_Alt_R,
+    XK_Super_L,
+    XK_Super_R,
+    XK_Meta_L,
+    XK_Meta_R,
+    XK_Hyper_L,
+    XK_Hyper_R,
+];
+
+/// 抓取时是否出现了 BadAccess, 即已经被其他程序抓取
+static mut GRAB_FAILED: bool = false;
+
+unsafe extern "C" fn on_error(
+    _display: *mut xlib::Display,
+    event: *mut xlib::XErrorEvent,
+) -> c_int {
+    if (*event).error_code == xlib::BadAccess {
+        GRAB_FAILED = true;
+    }
+
+    0
+}
+
+pub struct X11Hotkeys {
+    // 和 GTK 分开的连接, 只用来抓取热键
+    display: *mut xlib::Display,
+
+    // 注册的热键 id 对应的键码和修饰键
+    grabs: RefCell<HashMap<i32, (c_int, u32)>>,
+    // 正在按住的热键, 按住不放时的自动重复不再触发, 松开时通知
+    pressed: RefCell<HashSet<i32>>,
+}
+
+impl X11Hotkeys {
+    /// 打开单独的 X 连接, 在 GTK 的主循环里读取它的事件
+    pub fn open() -> Result<Rc<X11Hotkeys>> {
+        let display = unsafe { xlib::XOpenDisplay(null()) };
+        if display.is_null() {
+            return Err(anyhow::anyhow!("Open X display failed"));
+        }
+        // 按住不放时只收到重复的 KeyPress, 不会夹杂 KeyRelease
+        unsafe { xlib::XkbSetDetectableAutoRepeat(display, xlib::True, null_mut()) };
+
+        let hotkeys = Rc::new(X11Hotkeys {
+            display,
+            grabs: RefCell::new(HashMap::new()),
+            pressed: RefCell::new(HashSet::new()),
+        });
+
+        let fd = unsafe { xlib::XConnectionNumber(display) };
+        let this = hotkeys.clone();
+        glib::source::unix_fd_add_local(fd, glib::IOCondition::IN, move |_fd, _condition| {
+            this.dispatch_events();
+            glib::Continue(true)
+        });
+
+        Ok(hotkeys)
+    }
+
+    /// NumLock 所在的修饰键, 通常是 Mod2
+    fn numlock_mask(&self) -> u32 {
+        unsafe {
+            let keycode = xlib::XKeysymToKeycode(self.display, XK_Num_Lock as xlib::KeySym);
+            let map = xlib::XGetModifierMapping(self.display);
+            if map.is_null() {
+                return 0;
+            }
+
+            let per_modifier = (*map).max_keypermod as usize;
+            let keys = std::slice::from_raw_parts((*map).modifiermap, 8 * per_modifier);
+            let mask = keys
+                .chunks(per_modifier)
+                .position(|it| keycode != 0 && it.contains(&keycode))
+                .map_or(0, |i| 1 << i);
+            xlib::XFreeModifiermap(map);
+
+            mask
+        }
+    }
+
+    /// CapsLock 和 NumLock 打开时按键的修饰键状态不同, 需要把这些组合都抓取一遍
+    fn lock_variants(&self) -> [u32; 4] {
+        let numlock = self.numlock_mask();
+        [0, xlib::LockMask, numlock, xlib::LockMask | numlock]
+    }
+
+    fn grab(&self, keycode: c_int, modifiers: u32) -> bool {
+        unsafe {
+            let root = xlib::XDefaultRootWindow(self.display);
+
+            // 抓取失败是异步报告的, 只在这里临时替换错误处理, 不影响 GTK 的连接
+            GRAB_FAILED = false;
+            let old_handler = xlib::XSetErrorHandler(Some(on_error));
+            for &extra in self.lock_variants().iter() {
+                xlib::XGrabKey(
+                    self.display,
+                    keycode,
+                    modifiers | extra,
+                    root,
+                    xlib::True,
+                    xlib::GrabModeAsync,
+                    xlib::GrabModeAsync,
+                );
+            }
+            xlib::XSync(self.display, xlib::False);
+            xlib::XSetErrorHandler(old_handler);
+
+            if GRAB_FAILED {
+                self.ungrab(keycode, modifiers);
+                return false;
+            }
+        }
+
+        true
+    }
+
+    fn ungrab(&self, keycode: c_int, modifiers: u32) {
+        unsafe {
+            let root = xlib::XDefaultRootWindow(self.display);
+            for &extra in self.lock_variants().iter() {
+                xlib::XUngrabKey(self.display, keycode, modifiers | extra, root);
+            }
+            xlib::XFlush(self.display);
+        }
+    }
+
+    /// 热键对应的键码和修饰键, 没有 keysym 的按键 (如鼠标侧键) 无法抓取
+    fn resolve(&self, hotkey: &Hotkey) -> Option<(c_int, u32)> {
+        let keysym = hotkey.key.to_keysym()?;
+        let keycode = unsafe { xlib::XKeysymToKeycode(self.display, keysym as xlib::KeySym) };
+        if keycode == 0 {
+            return None;
+        }
+
+        Some((keycode as c_int, hotkey.modifiers.to_x11() as u32))
+    }
+
+    fn dispatch_events(&self) {
+        let ignored = self.lock_variants()[3];
+
+        while unsafe { xlib::XPending(self.display) } > 0 {
+            let mut event: xlib::XEvent = unsafe { std::mem::zeroed() };
+            unsafe { xlib::XNextEvent(self.display, &mut event) };
+
+            let kind = event.get_type();
+            if kind != xlib::KeyPress && kind != xlib::KeyRelease {
+                continue;
+            }
+
+            let key = unsafe { event.key };
+            if kind == xlib::KeyRelease {
+                self.release(key.keycode as c_int);
+                continue;
+            }
+
+            let grabbed = (key.keycode as c_int, key.state & !ignored);
+            let id = self
+                .grabs
+                .borrow()
+                .iter()
+                .find(|(_, it)| **it == grabbed)
+                .map(|(&id, _)| id);
+            if let Some(id) = id {
+                if self.pressed.borrow_mut().insert(id) {
+                    on_hotkey(id);
+                }
+            }
+        }
+    }
+
+    /// 热键按住时键盘被抓取, 松开的修饰键也会送到这里.
+    /// 按 `RELEASE_MODE` 决定松开修饰键是否算松开
+    fn release(&self, keycode: c_int) {
+        let keysym = unsafe { xlib::XkbKeycodeToKeysym(self.display, keycode as u8, 0, 0) };
+        let is_modifier = unsafe { RELEASE_MODE } == ReleaseMode::AnyKey
+            && MODIFIERS.iter().any(|&it| it as xlib::KeySym == keysym);
+
+        let released: Vec<i32> = {
+            let grabs = self.grabs.borrow();
+            let mut pressed = self.pressed.borrow_mut();
+            let released: Vec<i32> = pressed
+                .iter()
+                .filter(|id| match grabs.get(id) {
+                    Some(&(code, modifiers)) => {
+                        code == keycode || (is_modifier && modifiers != 0)
+                    }
+                    None => true,
+                })
+                .copied()
+                .collect();
+            for id in &released {
+                pressed.remove(id);
+            }
+            released
+        };
+
+        for id in released {
+            on_hotkey_release(id);
+        }
+    }
+}
+
+impl HotkeyBackend for X11Hotkeys {
+    fn register(&self, hotkey: &Hotkey, id: i32) -> bool {
+        let (keycode, modifiers) = match self.resolve(hotkey) {
+            Some(it) => it,
+            None => return false,
+        };
+
+        if !self.grab(keycode, modifiers) {
+            return false;
+        }
+        self.grabs.borrow_mut().insert(id, (keycode, modifiers));
+
+        true
+    }
+
+    fn unregister(&self, id: i32) -> bool {
+        let grab = self.grabs.borrow_mut().remove(&id);
+        match grab {
+            Some((keycode, modifiers)) => {
+                self.ungrab(keycode, modifiers);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 试着抓取一次, 成功说明没有被其他程序占用
+    fn can_register(&self, hotkey: &Hotkey) -> bool {
+        let (keycode, modifiers) = match self.resolve(hotkey) {
+            Some(it) => it,
+            None => return false,
+        };
+
+        if !self.grab(keycode, modifiers) {
+            return false;
+        }
+        self.ungrab(keycode, modifiers);
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+    use crate::utils::linux::tests::{wait_fired, MainContextGuard};
+
+    fn xdotool(args: &[&str]) {
+        let status = Command::new("xdotool")
+            .args(args)
+            .status()
+            .expect("run xdotool failed");
+        assert!(status.success(), "xdotool {:?} failed", args);
+    }
+
+    /// 按下再松开, 松开的顺序由 `release` 给出
+    fn press(keys: &str, release: &[&str]) {
+        xdotool(&["keydown", keys]);
+        for &keys in release {
+            xdotool(&["keyup", keys]);
+        }
+    }
+
+    /// 需要 Xvfb 和 xdotool, 见 README
+    #[test]
+    #[ignore]
+    fn grab_under_xvfb() {
+        let _context = MainContextGuard::acquire();
+        let hotkeys = X11Hotkeys::open().unwrap();
+        let hotkey: Hotkey = "Ctrl+Alt+M".parse().unwrap();
+        assert!(hotkeys.register(&hotkey, 1));
+
+        // 按住时的重复不再触发
+        xdotool(&["keydown", "ctrl+alt+m"]);
+        xdotool(&["keydown", "m"]);
+        xdotool(&["keyup", "m"]);
+        assert_eq!(wait_fired(2), vec![(1, true), (1, false)]);
+        xdotool(&["keyup", "ctrl+alt"]);
+        assert_eq!(wait_fired(0), vec![]);
+
+        // CapsLock 和 NumLock 打开时也能收到
+        for &lock in &["Caps_Lock", "Num_Lock"] {
+            xdotool(&["key", lock]);
+            press("ctrl+alt+m", &["m", "ctrl+alt"]);
+            assert_eq!(wait_fired(2), vec![(1, true), (1, false)], "{}", lock);
+            xdotool(&["key", lock]);
+        }
+
+        // 按住时切换 CapsLock 不算松开修饰键
+        xdotool(&["keydown", "ctrl+alt+m"]);
+        xdotool(&["key", "Caps_Lock"]);
+        assert_eq!(wait_fired(1), vec![(1, true)]);
+        xdotool(&["key", "Caps_Lock"]);
+        xdotool(&["keyup", "m"]);
+        assert_eq!(wait_fired(1), vec![(1, false)]);
+        xdotool(&["keyup", "ctrl+alt"]);
+
+        // 默认松开任意一个修饰键就算松开
+        press("ctrl+alt+m", &["ctrl"]);
+        assert_eq!(wait_fired(2), vec![(1, true), (1, false)]);
+        xdotool(&["keyup", "alt+m"]);
+
+        unsafe { RELEASE_MODE = ReleaseMode::MainKey };
+        press("ctrl+alt+m", &["ctrl+alt"]);
+        assert_eq!(wait_fired(1), vec![(1, true)]);
+        xdotool(&["keyup", "m"]);
+        assert_eq!(wait_fired(1), vec![(1, false)]);
+        unsafe { RELEASE_MODE = ReleaseMode::AnyKey };
+
+        // 已经被其他连接抓取的组合无法注册
+        let other = X11Hotkeys::open().unwrap();
+        assert!(!other.can_register(&hotkey));
+        assert!(!other.register(&hotkey, 2));
+
+        assert!(hotkeys.unregister(1));
+        assert!(other.can_register(&hotkey));
+        press("ctrl+alt+m", &["m", "ctrl+alt"]);
+        assert_eq!(wait_fired(0), vec![]);
+    }
+}
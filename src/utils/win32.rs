@@ -1,33 +1,60 @@
-use std::ffi::c_void;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{c_void, OsStr};
+use std::iter::once;
 use std::mem::{size_of, transmute};
-use std::ptr::null_mut;
+use std::os::windows::ffi::OsStrExt;
+use std::os::raw::c_int;
+use std::ptr::{null, null_mut};
 use std::rc::Rc;
 
 use gtk::prelude::*;
 use winapi::{
     shared::{
         minwindef::{LPARAM, LRESULT, UINT, WPARAM},
-        windef::HWND,
+        windef::{HHOOK, HWND, POINT},
     },
     um::{
-        shellapi::{Shell_NotifyIconA, NIF_ICON, NIF_MESSAGE, NIM_ADD, NOTIFYICONDATAA},
+        libloaderapi::GetModuleHandleW,
+        shellapi::{
+            Shell_NotifyIconA, Shell_NotifyIconW, NIF_ICON, NIF_MESSAGE, NIF_TIP, NIM_ADD,
+            NIM_MODIFY, NOTIFYICONDATAA, NOTIFYICONDATAW,
+        },
         winuser::{
-            CallWindowProcA, GetAsyncKeyState, GetWindowLongPtrA, LoadIconW, RegisterHotKey,
-            SetWindowLongPtrA, UnregisterHotKey, GWL_WNDPROC, IDI_APPLICATION, WM_APP, WM_HOTKEY,
-            WM_LBUTTONDBLCLK, WNDPROC,
+            AppendMenuW, CallNextHookEx, CallWindowProcA, CreatePopupMenu, DestroyMenu,
+            GetAsyncKeyState, GetCursorPos, GetWindowLongPtrA, LoadIconW, PostMessageW,
+            RegisterHotKey, SetForegroundWindow, SetWindowLongPtrA, SetWindowsHookExW,
+            TrackPopupMenu, UnhookWindowsHookEx, UnregisterHotKey, GWL_WNDPROC, HC_ACTION,
+            IDI_APPLICATION, IDI_HAND, MF_CHECKED, MF_STRING, TPM_NONOTIFY, TPM_RETURNCMD,
+            WH_KEYBOARD_LL, WM_APP, WM_HOTKEY, WM_KEYUP, WM_LBUTTONDBLCLK, WM_RBUTTONUP,
+            WM_SYSKEYUP, WNDPROC,
         },
     },
 };
 
+use crate::hotkey::Hotkey;
+use crate::ptt::Chord;
 use crate::view::*;
 
-use super::MAIN_VIEW;
+use super::{MAIN_VIEW, RELEASE_MODE};
 
 pub static mut VIEW_HWND: Option<HWND> = None;
 pub static mut GTK_PROC: WNDPROC = None;
 
 const WM_TRAY: UINT = WM_APP + 1;
 
+/// 键盘钩子看到有键松开, 回到窗口过程里再检查按住的热键
+const WM_KEY_RELEASE: UINT = WM_APP + 2;
+
+/// 注册成功的热键, 用来判断按住的热键是否已经松开
+static mut HOTKEYS: Option<HashMap<i32, Hotkey>> = None;
+
+/// WM_HOTKEY 只有按下没有松开, 按住说话和咳嗽键按下后临时安装低级键盘钩子等待松开, 全部松开后卸载
+static mut HELD: Option<HashSet<i32>> = None;
+static mut KEYBOARD_HOOK: Option<HHOOK> = None;
+
+/// 托盘菜单中配置方案的命令 id 从这里开始, 0 表示没有选择
+const PROFILE_MENU_ID: usize = 1;
+
 /// 为了创建托盘图标以及快捷键等, 需要获取到窗体的 HWND, gtk-sys 没有暴露对应的方法
 /// 只能自己加 extern "C" 来导入
 #[link(name = "gdk-3")]
@@ -35,12 +62,39 @@ extern "C" {
     pub fn gdk_win32_window_get_handle(window: *mut c_void) -> HWND;
 }
 
-pub fn register_hotkey(mod_code: u32, key_code: u32, id: i32) -> bool {
-    unsafe { RegisterHotKey(VIEW_HWND.unwrap(), id, mod_code, key_code) == 1 }
+pub fn register_hotkey(hotkey: &Hotkey, id: i32) -> bool {
+    let key_code = match hotkey.key.to_vk() {
+        Some(code) => code,
+        None => return false,
+    };
+
+    unsafe {
+        if RegisterHotKey(VIEW_HWND.unwrap(), id, hotkey.modifiers.to_win32(), key_code) != 1 {
+            return false;
+        }
+        HOTKEYS.get_or_insert_with(HashMap::new).insert(id, *hotkey);
+    }
+
+    true
 }
 
 pub fn unregister_hotkey(id: i32) -> bool {
-    unsafe { UnregisterHotKey(VIEW_HWND.unwrap(), id) == 1 }
+    unsafe {
+        HOTKEYS.as_mut().map(|it| it.remove(&id));
+        UnregisterHotKey(VIEW_HWND.unwrap(), id) == 1
+    }
+}
+
+/// 用一个不会收到消息的 id 试着注册一次, 注册成功说明没有被其他程序占用
+pub fn can_register(hotkey: &Hotkey) -> bool {
+    const PROBE_ID: i32 = 0xBFFF;
+
+    if !register_hotkey(hotkey, PROBE_ID) {
+        return false;
+    }
+    unregister_hotkey(PROBE_ID);
+
+    true
 }
 
 pub fn init_tray(view: Rc<View>) {
@@ -61,6 +115,37 @@ pub fn init_tray(view: Rc<View>) {
     };
 }
 
+/// 静音时托盘图标换成红色的叉, 提示文字显示当前状态
+pub fn update_tray(mute: bool) {
+    let hwnd = match unsafe { VIEW_HWND } {
+        Some(hwnd) => hwnd,
+        None => return,
+    };
+
+    let (icon, tip) = if mute {
+        (IDI_HAND, "Mute Assistant - 静音")
+    } else {
+        (IDI_APPLICATION, "Mute Assistant - 正常")
+    };
+
+    unsafe {
+        let mut nid = NOTIFYICONDATAW::default();
+        nid.cbSize = size_of::<NOTIFYICONDATAW>() as u32;
+        nid.hWnd = hwnd;
+        nid.uID = 1;
+        nid.uFlags = NIF_ICON | NIF_TIP;
+        nid.hIcon = LoadIconW(null_mut(), icon);
+
+        // 提示文字需要以 0 结尾, 留出最后一个位置
+        let len = nid.szTip.len() - 1;
+        for (dst, src) in nid.szTip[..len].iter_mut().zip(OsStr::new(tip).encode_wide()) {
+            *dst = src;
+        }
+
+        Shell_NotifyIconW(NIM_MODIFY, &mut nid);
+    }
+}
+
 pub fn init(_view: Rc<View>) {
     // 由于需要接受热键的消息, 需要处理 WM_HOTKEY 消息, gtk::timeout_add 和 PeekMessage 的方式似乎会漏掉很多消息.
     // 而 gtk::idle_add 会导致 CPU 占用率显著上升, 并且也会漏掉消息.
@@ -72,15 +157,130 @@ pub fn init(_view: Rc<View>) {
     }
 }
 
-pub fn is_key_release(codes: &[u32]) -> bool {
-    let mut release = false;
-    for &code in codes {
-        let r = unsafe { GetAsyncKeyState(code as i32) };
-        let r = (r >> 8) == 0;
-        release |= r;
+/// 左右两侧的修饰键按下其中一个即可, 是否要求修饰键一直按住由 `RELEASE_MODE` 决定
+unsafe fn is_key_release(hotkey: &Hotkey) -> bool {
+    let is_down = |code: u32| (GetAsyncKeyState(code as i32) >> 8) != 0;
+
+    Chord::from_vk(hotkey).map_or(true, |chord| chord.is_released(RELEASE_MODE, is_down))
+}
+
+unsafe fn on_hotkey_press(id: i32) {
+    // 只有按住说话和咳嗽键需要知道什么时候松开, 其他热键不安装钩子
+    if id != PUSH_HOTKEY && id != COUGH_HOTKEY {
+        MAIN_VIEW.as_ref().map(|view| view.on_hotkey(id));
+        return;
+    }
+
+    HELD.get_or_insert_with(HashSet::new).insert(id);
+
+    if KEYBOARD_HOOK.is_none() {
+        let module = GetModuleHandleW(null());
+        let hook = SetWindowsHookExW(WH_KEYBOARD_LL, Some(keyboard_hook), module, 0);
+        if hook.is_null() {
+            eprintln!("Install keyboard hook failed, hotkey is released at once");
+        } else {
+            KEYBOARD_HOOK = Some(hook);
+        }
+    }
+
+    MAIN_VIEW.as_ref().map(|view| view.on_hotkey(id));
+
+    if KEYBOARD_HOOK.is_none() {
+        // 没有钩子就收不到松开, 立即当作松开, 不让麦克风一直开着
+        release_all();
+    } else {
+        // 快速按一下时, 钩子安装好之前按键可能已经松开了
+        check_released();
+    }
+}
+
+/// 钩子里不做耗时的操作, 只通知窗口
+unsafe extern "system" fn keyboard_hook(code: c_int, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    let key_up = wparam as UINT == WM_KEYUP || wparam as UINT == WM_SYSKEYUP;
+    if code == HC_ACTION && key_up {
+        if let Some(hwnd) = VIEW_HWND {
+            PostMessageW(hwnd, WM_KEY_RELEASE, 0, 0);
+        }
     }
 
-    release
+    CallNextHookEx(null_mut(), code, wparam, lparam)
+}
+
+unsafe fn check_released() {
+    let held = match HELD.as_mut() {
+        Some(held) => held,
+        None => return,
+    };
+
+    let released: Vec<i32> = held
+        .iter()
+        .copied()
+        .filter(|id| {
+            HOTKEYS
+                .as_ref()
+                .and_then(|it| it.get(id))
+                .map_or(true, |it| is_key_release(it))
+        })
+        .collect();
+    for id in &released {
+        held.remove(id);
+    }
+
+    if held.is_empty() {
+        if let Some(hook) = KEYBOARD_HOOK.take() {
+            UnhookWindowsHookEx(hook);
+        }
+    }
+
+    for id in released {
+        MAIN_VIEW.as_ref().map(|view| view.on_hotkey_release(id));
+    }
+}
+
+unsafe fn release_all() {
+    let held = HELD.take().unwrap_or_default();
+    for id in held {
+        MAIN_VIEW.as_ref().map(|view| view.on_hotkey_release(id));
+    }
+}
+
+/// 右键托盘图标时弹出配置方案菜单, 当前方案打勾
+unsafe fn show_profile_menu(hwnd: HWND) {
+    let view = match MAIN_VIEW.as_ref() {
+        Some(view) => view,
+        None => return,
+    };
+    let (names, current) = view.profiles();
+
+    let menu = CreatePopupMenu();
+    for (i, name) in names.iter().enumerate() {
+        let flags = if *name == current {
+            MF_STRING | MF_CHECKED
+        } else {
+            MF_STRING
+        };
+        let text: Vec<u16> = OsStr::new(name).encode_wide().chain(once(0)).collect();
+        AppendMenuW(menu, flags, PROFILE_MENU_ID + i, text.as_ptr());
+    }
+
+    let mut point = POINT::default();
+    GetCursorPos(&mut point);
+    // 不先把窗口设为前台的话, 点击菜单以外的地方菜单不会消失
+    SetForegroundWindow(hwnd);
+    let cmd = TrackPopupMenu(
+        menu,
+        TPM_RETURNCMD | TPM_NONOTIFY,
+        point.x,
+        point.y,
+        0,
+        hwnd,
+        null(),
+    ) as usize;
+    DestroyMenu(menu);
+
+    if let Some(name) = cmd.checked_sub(PROFILE_MENU_ID).and_then(|i| names.get(i)) {
+        view.switch_profile(name);
+    }
 }
 
 unsafe extern "system" fn proc_wrapper(
@@ -94,17 +294,11 @@ unsafe extern "system" fn proc_wrapper(
             WM_LBUTTONDBLCLK => {
                 MAIN_VIEW.as_ref().map(|view| view.show());
             }
+            WM_RBUTTONUP => show_profile_menu(hwnd),
             _ => {}
         },
-        WM_HOTKEY => match wparam {
-            1 => {
-                MAIN_VIEW.as_ref().map(|view| view.toggle());
-            }
-            2 => {
-                MAIN_VIEW.as_ref().map(|view| view.unmute());
-            }
-            _ => {}
-        },
+        WM_HOTKEY => on_hotkey_press(wparam as i32),
+        WM_KEY_RELEASE => check_released(),
         _ => {
             if let Some(ref _proc) = GTK_PROC {
                 return CallWindowProcA(GTK_PROC, hwnd, msg, wparam, lparam);
@@ -1,467 +1,653 @@
 use gtk::prelude::*;
 use gtk::{
-    Application, Builder, Button, ButtonsType, CheckButton, ComboBoxText, DialogFlags, Label,
-    MessageDialog, MessageType, Switch, Window,
+    Application, Builder, Button, ButtonsType, CheckButton, ComboBoxText, Dialog, DialogFlags,
+    Entry, Label, ListBox, MessageDialog, MessageType, Orientation, ResponseType, Scale, Switch,
+    Window,
 };
 
-use lazy_static::lazy_static;
-
 use anyhow::{Context, Result};
 
-use crate::utils::{register_hotkey, unregister_hotkey};
-use crate::volume::Volume;
+use crate::config::{Config, GainHotkeyConfig, HotkeyConfig, MuteStrategy};
+use crate::hotkey::Hotkey;
+use crate::ptt::{Action, PttMachine};
+use crate::utils::{
+    can_register, register_hotkey, unregister_hotkey, update_tray, RELEASE_MODE,
+};
+use crate::volume::{MicBackend, MuteScope, MuteSnapshot, MuteState, ScopeMode, VolumeEvent};
+use gio::FileMonitor;
 use glib::WeakRef;
-use std::collections::BTreeMap;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
-lazy_static! {
-    static ref KEY_MAP: BTreeMap<u32, &'static str> = {
-        let mut map = BTreeMap::new();
-
-        map.insert(0x01, "Left mouse button");
-        map.insert(0x02, "Right mouse button");
-        map.insert(0x03, "Control-break processing");
-        map.insert(0x04, "Middle mouse button (three-button mouse)");
-        map.insert(0x05, "X1 mouse button");
-        map.insert(0x06, "X2 mouse button");
-        map.insert(0x08, "BACKSPACE key");
-        map.insert(0x09, "TAB key");
-        map.insert(0x0C, "CLEAR key");
-        map.insert(0x0D, "ENTER key");
-        map.insert(0x10, "SHIFT key");
-        map.insert(0x11, "CTRL key");
-        map.insert(0x12, "ALT key");
-        map.insert(0x13, "PAUSE key");
-        map.insert(0x14, "CAPS LOCK key");
-        map.insert(0x15, "IME Kana mode");
-        map.insert(
-            0x15,
-            "IME Hanguel mode (maintained for compatibility; use VK_HANGUL)",
-        );
-        map.insert(0x15, "IME Hangul mode");
-        map.insert(0x16, "IME On");
-        map.insert(0x17, "IME Junja mode");
-        map.insert(0x18, "IME final mode");
-        map.insert(0x19, "IME Hanja mode");
-        map.insert(0x19, "IME Kanji mode");
-        map.insert(0x1A, "IME Off");
-        map.insert(0x1B, "ESC key");
-        map.insert(0x1C, "IME convert");
-        map.insert(0x1D, "IME nonconvert");
-        map.insert(0x1E, "IME accept");
-        map.insert(0x1F, "IME mode change request");
-        map.insert(0x20, "SPACEBAR");
-        map.insert(0x21, "PAGE UP key");
-        map.insert(0x22, "PAGE DOWN key");
-        map.insert(0x23, "END key");
-        map.insert(0x24, "HOME key");
-        map.insert(0x25, "LEFT ARROW key");
-        map.insert(0x26, "UP ARROW key");
-        map.insert(0x27, "RIGHT ARROW key");
-        map.insert(0x28, "DOWN ARROW key");
-        map.insert(0x29, "SELECT key");
-        map.insert(0x2A, "PRINT key");
-        map.insert(0x2B, "EXECUTE key");
-        map.insert(0x2C, "PRINT SCREEN key");
-        map.insert(0x2D, "INS key");
-        map.insert(0x2E, "DEL key");
-        map.insert(0x2F, "HELP key");
-        map.insert(0x30, "0 key");
-        map.insert(0x31, "1 key");
-        map.insert(0x32, "2 key");
-        map.insert(0x33, "3 key");
-        map.insert(0x34, "4 key");
-        map.insert(0x35, "5 key");
-        map.insert(0x36, "6 key");
-        map.insert(0x37, "7 key");
-        map.insert(0x38, "8 key");
-        map.insert(0x39, "9 key");
-        map.insert(0x41, "A key");
-        map.insert(0x42, "B key");
-        map.insert(0x43, "C key");
-        map.insert(0x44, "D key");
-        map.insert(0x45, "E key");
-        map.insert(0x46, "F key");
-        map.insert(0x47, "G key");
-        map.insert(0x48, "H key");
-        map.insert(0x49, "I key");
-        map.insert(0x4A, "J key");
-        map.insert(0x4B, "K key");
-        map.insert(0x4C, "L key");
-        map.insert(0x4D, "M key");
-        map.insert(0x4E, "N key");
-        map.insert(0x4F, "O key");
-        map.insert(0x50, "P key");
-        map.insert(0x51, "Q key");
-        map.insert(0x52, "R key");
-        map.insert(0x53, "S key");
-        map.insert(0x54, "T key");
-        map.insert(0x55, "U key");
-        map.insert(0x56, "V key");
-        map.insert(0x57, "W key");
-        map.insert(0x58, "X key");
-        map.insert(0x59, "Y key");
-        map.insert(0x5A, "Z key");
-        map.insert(0x5B, "Left Windows key (Natural keyboard)");
-        map.insert(0x5C, "Right Windows key (Natural keyboard)");
-        map.insert(0x5D, "Applications key (Natural keyboard)");
-        map.insert(0x5F, "Computer Sleep key");
-        map.insert(0x60, "Numeric keypad 0 key");
-        map.insert(0x61, "Numeric keypad 1 key");
-        map.insert(0x62, "Numeric keypad 2 key");
-        map.insert(0x63, "Numeric keypad 3 key");
-        map.insert(0x64, "Numeric keypad 4 key");
-        map.insert(0x65, "Numeric keypad 5 key");
-        map.insert(0x66, "Numeric keypad 6 key");
-        map.insert(0x67, "Numeric keypad 7 key");
-        map.insert(0x68, "Numeric keypad 8 key");
-        map.insert(0x69, "Numeric keypad 9 key");
-        map.insert(0x6A, "Multiply key");
-        map.insert(0x6B, "Add key");
-        map.insert(0x6C, "Separator key");
-        map.insert(0x6D, "Subtract key");
-        map.insert(0x6E, "Decimal key");
-        map.insert(0x6F, "Divide key");
-        map.insert(0x70, "F1 key");
-        map.insert(0x71, "F2 key");
-        map.insert(0x72, "F3 key");
-        map.insert(0x73, "F4 key");
-        map.insert(0x74, "F5 key");
-        map.insert(0x75, "F6 key");
-        map.insert(0x76, "F7 key");
-        map.insert(0x77, "F8 key");
-        map.insert(0x78, "F9 key");
-        map.insert(0x79, "F10 key");
-        map.insert(0x7A, "F11 key");
-        map.insert(0x7B, "F12 key");
-        map.insert(0x7C, "F13 key");
-        map.insert(0x7D, "F14 key");
-        map.insert(0x7E, "F15 key");
-        map.insert(0x7F, "F16 key");
-        map.insert(0x80, "F17 key");
-        map.insert(0x81, "F18 key");
-        map.insert(0x82, "F19 key");
-        map.insert(0x83, "F20 key");
-        map.insert(0x84, "F21 key");
-        map.insert(0x85, "F22 key");
-        map.insert(0x86, "F23 key");
-        map.insert(0x87, "F24 key");
-        map.insert(0x90, "NUM LOCK key");
-        map.insert(0x91, "SCROLL LOCK key");
-        map.insert(0xA0, "Left SHIFT key");
-        map.insert(0xA1, "Right SHIFT key");
-        map.insert(0xA2, "Left CONTROL key");
-        map.insert(0xA3, "Right CONTROL key");
-        map.insert(0xA4, "Left MENU key");
-        map.insert(0xA5, "Right MENU key");
-        map.insert(0xA6, "Browser Back key");
-        map.insert(0xA7, "Browser Forward key");
-        map.insert(0xA8, "Browser Refresh key");
-        map.insert(0xA9, "Browser Stop key");
-        map.insert(0xAA, "Browser Search key");
-        map.insert(0xAB, "Browser Favorites key");
-        map.insert(0xAC, "Browser Start and Home key");
-        map.insert(0xAD, "Volume Mute key");
-        map.insert(0xAE, "Volume Down key");
-        map.insert(0xAF, "Volume Up key");
-        map.insert(0xB0, "Next Track key");
-        map.insert(0xB1, "Previous Track key");
-        map.insert(0xB2, "Stop Media key");
-        map.insert(0xB3, "Play/Pause Media key");
-        map.insert(0xB4, "Start Mail key");
-        map.insert(0xB5, "Select Media key");
-        map.insert(0xB6, "Start Application 1 key");
-        map.insert(0xB7, "Start Application 2 key");
-
-        map
-    };
+use capture::HotkeyCapture;
+
+mod capture;
+
+/// 音量快捷键每次调整的幅度
+pub const GAIN_STEP: f32 = 0.05;
+
+/// 注册快捷键时使用的 id, 各平台收到快捷键后以此调用 `on_hotkey`
+pub const MUTE_HOTKEY: i32 = 1;
+pub const PUSH_HOTKEY: i32 = 2;
+pub const GAIN_UP_HOTKEY: i32 = 3;
+pub const GAIN_DOWN_HOTKEY: i32 = 4;
+pub const PROFILE_HOTKEY: i32 = 5;
+pub const COUGH_HOTKEY: i32 = 6;
+
+/// 已启用的快捷键和注册时使用的 id
+fn bound_hotkeys(config: &Config) -> Vec<(i32, &'static str, Hotkey)> {
+    let hotkeys = config.current().hotkeys;
+    let gain = &hotkeys.gain;
+
+    let bound = [
+        (MUTE_HOTKEY, "静音快捷键", hotkeys.mute.enabled, hotkeys.mute.hotkey),
+        (PUSH_HOTKEY, "按住说话", hotkeys.push.enabled, hotkeys.push.hotkey),
+        (COUGH_HOTKEY, "按住静音", hotkeys.cough.enabled, hotkeys.cough.hotkey),
+        (GAIN_UP_HOTKEY, "增大输入音量", gain.enabled, gain.up),
+        (GAIN_DOWN_HOTKEY, "减小输入音量", gain.enabled, gain.down),
+        (
+            PROFILE_HOTKEY,
+            "切换配置方案",
+            config.profile_hotkey.enabled,
+            config.profile_hotkey.hotkey,
+        ),
+    ];
+
+    bound
+        .iter()
+        .filter_map(|&(id, name, enabled, hotkey)| match (enabled, hotkey) {
+            (true, Some(hotkey)) => Some((id, name, hotkey)),
+            _ => None,
+        })
+        .collect()
 }
 
-pub struct ModKeyView {
-    ctrl: CheckButton,
-    alt: CheckButton,
-    shift: CheckButton,
-    win: CheckButton,
+/// 检查录制到的组合键, 返回给用户的提示. `id` 是这个快捷键自己注册时使用的 id
+fn check_hotkey(config: &RefCell<Config>, id: i32, hotkey: Hotkey) -> Option<String> {
+    if let Some(reason) = hotkey.reserved() {
+        return Some(format!("{} {}", hotkey, reason));
+    }
+
+    let bound = bound_hotkeys(&config.borrow());
+    for &(other, name, it) in &bound {
+        if other != id && it == hotkey {
+            return Some(format!("{} 已被{}使用", hotkey, name));
+        }
+    }
+
+    // 自己已经注册了这个组合时不能再试着注册
+    let registered = bound.iter().any(|&(other, _, it)| other == id && it == hotkey);
+    if !registered && !can_register(&hotkey) {
+        return Some(format!("{} 已被其他程序占用", hotkey));
+    }
+
+    None
 }
 
-pub trait MuteViewExt {
-    fn init(&self, app: &Application);
+/// 创建录制快捷键的按钮, 按钮和提示的 id 分别为 `{name}_capture` 和 `{warning}`
+fn new_capture(
+    builder: &Builder,
+    name: &str,
+    warning: &str,
+    id: i32,
+    config: &Rc<RefCell<Config>>,
+) -> Result<Rc<HotkeyCapture>> {
+    let button = builder
+        .get_object(&format!("{}_capture", name))
+        .with_context(|| format!("{} capture", name))?;
+    let warning = builder.get_object(warning).context("warning")?;
+
+    let config = config.clone();
+    Ok(HotkeyCapture::new(button, warning, move |hotkey| {
+        check_hotkey(&config, id, hotkey)
+    }))
+}
 
-    fn update_hotkey(&self) -> bool;
+/// 显示在提示中的快捷键, 如 `Ctrl+Alt+M`
+fn hotkey_text(hotkey: Option<Hotkey>) -> String {
+    hotkey.map(|it| it.to_string()).unwrap_or_default()
 }
 
-pub struct MuteView {
-    mute_btn: Switch,
+/// 修改配置后立即写入配置文件
+fn update_config(config: &RefCell<Config>, f: impl FnOnce(&mut Config)) {
+    let mut config = config.borrow_mut();
+    f(&mut config);
+
+    if let Err(e) = config.save() {
+        eprintln!("Save config failed: {:#}", e);
+    }
+}
 
-    mute_mod: ModKeyView,
-    mute_key: ComboBoxText,
+/// 一行快捷键设置对应的配置项
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotkeySlot {
+    Mute,
+    Push,
+    Cough,
+    Gain,
+    /// 依次切换配置方案的快捷键, 不随方案变化
+    Profile,
+}
 
-    mute_key_set: Button,
+impl HotkeySlot {
+    /// 界面中控件 id 的前缀
+    fn name(self) -> &'static str {
+        match self {
+            HotkeySlot::Mute => "mute",
+            HotkeySlot::Push => "push",
+            HotkeySlot::Cough => "cough",
+            HotkeySlot::Gain => "gain",
+            HotkeySlot::Profile => "profile",
+        }
+    }
+
+    /// 注册时使用的 id 和录制按钮的名字, 见 `new_capture`
+    fn captures(self) -> &'static [(i32, &'static str)] {
+        match self {
+            HotkeySlot::Mute => &[(MUTE_HOTKEY, "mute")],
+            HotkeySlot::Push => &[(PUSH_HOTKEY, "push")],
+            HotkeySlot::Cough => &[(COUGH_HOTKEY, "cough")],
+            HotkeySlot::Gain => &[(GAIN_UP_HOTKEY, "gain_up"), (GAIN_DOWN_HOTKEY, "gain_down")],
+            HotkeySlot::Profile => &[(PROFILE_HOTKEY, "profile")],
+        }
+    }
+
+    /// 是否启用, 以及按录制按钮顺序排列的快捷键
+    fn get(self, config: &Config) -> (bool, Vec<Option<Hotkey>>) {
+        let hotkeys = config.current().hotkeys;
+        let single = |it: HotkeyConfig| (it.enabled, vec![it.hotkey]);
+        match self {
+            HotkeySlot::Mute => single(hotkeys.mute),
+            HotkeySlot::Push => single(hotkeys.push),
+            HotkeySlot::Cough => single(hotkeys.cough),
+            HotkeySlot::Gain => (hotkeys.gain.enabled, vec![hotkeys.gain.up, hotkeys.gain.down]),
+            HotkeySlot::Profile => single(config.profile_hotkey.clone()),
+        }
+    }
+
+    fn set(self, config: &mut Config, enabled: bool, hotkeys: &[Option<Hotkey>]) {
+        let hotkey = HotkeyConfig::new(enabled, hotkeys[0]);
+        match self {
+            HotkeySlot::Mute => config.current_mut().hotkeys.mute = hotkey,
+            HotkeySlot::Push => config.current_mut().hotkeys.push = hotkey,
+            HotkeySlot::Cough => config.current_mut().hotkeys.cough = hotkey,
+            HotkeySlot::Gain => {
+                let gain = GainHotkeyConfig::new(enabled, hotkeys[0], hotkeys[1]);
+                config.current_mut().hotkeys.gain = gain;
+            }
+            HotkeySlot::Profile => config.profile_hotkey = hotkey,
+        }
+    }
+}
+
+/// 一行快捷键设置: 启用开关, 录制按钮和设置按钮. 一行可以有多个快捷键 (如增大和减小音量),
+/// 它们一起启用, 要么都注册成功, 要么都不注册
+pub struct HotkeyRow {
+    enable_btn: Switch,
+
+    // 注册时使用的 id 和对应的录制按钮
+    captures: Vec<(i32, Rc<HotkeyCapture>)>,
+
+    key_set: Button,
+
+    // 从配置恢复控件状态时不注册快捷键, 也不写回配置
+    loading: Cell<bool>,
+
+    slot: HotkeySlot,
 
     window: WeakRef<Window>,
-    volume: Rc<Volume>,
+    config: Rc<RefCell<Config>>,
 }
 
-impl MuteView {
-    pub fn new(
-        builder: &Builder,
-        window: WeakRef<Window>,
-        volume: Rc<Volume>,
-    ) -> Result<Rc<MuteView>> {
-        let mute_btn = builder.get_object("mute_btn").context("mute btn")?;
-
-        let ctrl = builder.get_object("mute_ctrl").context("ctrl")?;
-        let alt = builder.get_object("mute_alt").context("alt")?;
-        let shift = builder.get_object("mute_shift").context("shift")?;
-        let win = builder.get_object("mute_win").context("win")?;
-
-        let mute_key: ComboBoxText = builder.get_object("mute_key").context("key")?;
-        let mute_key_set = builder.get_object("hotkey_set").unwrap();
-
-        for (code, desc) in KEY_MAP.iter() {
-            mute_key.append(Some(&code.to_string()), desc);
-        }
-        mute_key.set_active(Some(0));
-
-        let view = MuteView {
-            mute_btn,
-            mute_mod: ModKeyView {
-                ctrl,
-                alt,
-                shift,
-                win,
-            },
-            mute_key,
-            mute_key_set,
-            window,
-            volume,
-        };
+pub trait HotkeyRowExt {
+    fn init(&self);
 
-        Ok(Rc::new(view))
-    }
+    fn update_hotkey(&self) -> bool;
+
+    fn unregister(&self);
+
+    fn apply(&self) -> bool;
+
+    fn warn(&self, id: i32, text: &str) -> bool;
+
+    fn load(&self);
+
+    fn save(&self);
 }
 
-impl MuteViewExt for Rc<MuteView> {
-    fn init(&self, _app: &Application) {
-        self.mute_btn.connect_changed_active(|_this| {});
+impl HotkeyRowExt for Rc<HotkeyRow> {
+    fn init(&self) {
+        self.load();
 
         let this = self.clone();
-        self.mute_key_set.connect_clicked(move |_it| {
-            if this.update_hotkey() {
-                this.mute_btn.set_active(true);
-                let dialog = MessageDialog::new(
-                    this.window.upgrade().as_ref(),
-                    DialogFlags::MODAL,
-                    MessageType::Info,
-                    ButtonsType::Ok,
-                    "设置成功",
-                );
-                dialog.connect_response(|dialog, _| {
-                    dialog.emit_close();
-                });
-                dialog.run();
-            } else {
-                this.mute_btn.set_active(false);
-                let dialog = MessageDialog::new(
-                    this.window.upgrade().as_ref(),
-                    DialogFlags::MODAL,
-                    MessageType::Error,
-                    ButtonsType::Ok,
-                    "设置失败, 快捷键已被占用",
-                );
-                dialog.connect_response(|dialog, _| {
-                    dialog.emit_close();
-                });
-                dialog.run();
+        self.key_set.connect_clicked(move |_it| {
+            let hotkeys: Vec<Option<Hotkey>> =
+                this.captures.iter().map(|(_, it)| it.hotkey()).collect();
+            if let Some(pos) = hotkeys.iter().position(Option::is_none) {
+                this.captures[pos].1.warn("请先点击按钮录制快捷键");
+                return;
             }
+
+            let text: Vec<String> = hotkeys.into_iter().map(hotkey_text).collect();
+            let text = text.join(" / ");
+            let (kind, message) = if this.update_hotkey() {
+                this.enable_btn.set_active(true);
+                (MessageType::Info, format!("设置成功: {}", text))
+            } else {
+                this.enable_btn.set_active(false);
+                (MessageType::Error, format!("设置失败, {} 已被占用", text))
+            };
+            this.save();
+
+            let dialog = MessageDialog::new(
+                this.window.upgrade().as_ref(),
+                DialogFlags::MODAL,
+                kind,
+                ButtonsType::Ok,
+                &message,
+            );
+            dialog.connect_response(|dialog, _| {
+                dialog.emit_close();
+            });
+            dialog.run();
         });
 
         let this = self.clone();
-        self.mute_btn.connect_state_set(move |_it, enable| {
+        self.enable_btn.connect_state_set(move |_it, enable| {
+            if this.loading.get() {
+                return Inhibit(false);
+            }
+
             if enable {
                 this.update_hotkey();
             } else {
-                unregister_hotkey(1);
+                this.unregister();
             }
+            this.save();
 
             Inhibit(false)
         });
     }
 
     fn update_hotkey(&self) -> bool {
-        let key_code = self
-            .mute_key
-            .get_active_id()
-            .and_then(|it| it.as_str().parse::<u32>().ok())
-            .unwrap();
+        let mut hotkeys = Vec::new();
+        for (id, capture) in &self.captures {
+            match capture.hotkey() {
+                Some(hotkey) => hotkeys.push((*id, hotkey)),
+                None => return false,
+            }
+        }
+
+        self.unregister();
+        for (i, (id, hotkey)) in hotkeys.iter().enumerate() {
+            if !register_hotkey(hotkey, *id) {
+                for (id, _) in &hotkeys[..i] {
+                    unregister_hotkey(*id);
+                }
+                return false;
+            }
+        }
 
-        let mod_code = (self.mute_mod.ctrl.get_active() as u32) << 1
-            | (self.mute_mod.alt.get_active() as u32) << 0
-            | (self.mute_mod.shift.get_active() as u32) << 2
-            | (self.mute_mod.win.get_active() as u32) << 3
-            | 0x4000;
+        true
+    }
 
-        unregister_hotkey(1);
-        register_hotkey(mod_code, key_code, 1)
+    fn unregister(&self) {
+        for (id, _) in &self.captures {
+            unregister_hotkey(*id);
+        }
+    }
+
+    /// 按开关状态注册或者注销快捷键, 注册失败时返回 false
+    fn apply(&self) -> bool {
+        if self.enable_btn.get_active() {
+            self.update_hotkey()
+        } else {
+            self.unregister();
+            true
+        }
+    }
+
+    /// 在 `id` 对应的录制按钮旁显示提示, 不是这一行的快捷键时返回 false
+    fn warn(&self, id: i32, text: &str) -> bool {
+        match self.captures.iter().find(|it| it.0 == id) {
+            Some((_, capture)) => {
+                capture.warn(text);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn load(&self) {
+        let (enabled, hotkeys) = self.slot.get(&self.config.borrow());
+
+        self.loading.set(true);
+        for ((_, capture), hotkey) in self.captures.iter().zip(hotkeys) {
+            capture.set_hotkey(hotkey);
+        }
+        self.enable_btn.set_active(enabled);
+        self.loading.set(false);
+    }
+
+    fn save(&self) {
+        let enabled = self.enable_btn.get_active();
+        let hotkeys: Vec<Option<Hotkey>> =
+            self.captures.iter().map(|(_, it)| it.hotkey()).collect();
+
+        update_config(&self.config, |config| self.slot.set(config, enabled, &hotkeys));
     }
 }
 
-pub struct PushView {
-    push_btn: Switch,
+impl HotkeyRow {
+    pub fn new(
+        builder: &Builder,
+        slot: HotkeySlot,
+        window: WeakRef<Window>,
+        config: Rc<RefCell<Config>>,
+    ) -> Result<Rc<HotkeyRow>> {
+        let name = slot.name();
+        let enable_btn = builder
+            .get_object(&format!("{}_btn", name))
+            .with_context(|| format!("{} btn", name))?;
+        let key_set = builder
+            .get_object(&format!("{}_hotkey_set", name))
+            .with_context(|| format!("{} hotkey set", name))?;
+
+        let warning = format!("{}_warning", name);
+        let captures = slot
+            .captures()
+            .iter()
+            .map(|&(id, capture)| Ok((id, new_capture(builder, capture, &warning, id, &config)?)))
+            .collect::<Result<_>>()?;
+
+        let row = HotkeyRow {
+            enable_btn,
+            captures,
+            key_set,
+            loading: Cell::new(false),
+            slot,
+            window,
+            config,
+        };
+
+        Ok(Rc::new(row))
+    }
+}
 
-    push_mod: ModKeyView,
-    push_key: ComboBoxText,
+pub struct DeviceView {
+    device: ComboBoxText,
+    strategy: ComboBoxText,
 
-    push_key_set: Button,
+    // 用户选择的设备 id, None 表示跟随系统默认设备
+    selected: RefCell<Option<String>>,
+    // 重新填充列表时会触发 changed 信号, 此时不应切换设备
+    updating: Cell<bool>,
 
     window: WeakRef<Window>,
+    volume: Rc<dyn MicBackend>,
+    config: Rc<RefCell<Config>>,
 }
 
-pub trait PushViewExt {
+pub trait DeviceViewExt {
     fn init(&self);
 
-    fn update_hotkey(&self) -> bool;
+    fn refresh(&self);
+
+    fn refresh_strategy(&self);
+
+    fn load(&self);
 }
 
-impl PushViewExt for Rc<PushView> {
+impl DeviceViewExt for Rc<DeviceView> {
     fn init(&self) {
+        for &(strategy, desc) in &[
+            (MuteStrategy::Auto, "自动检测"),
+            (MuteStrategy::Native, "设备静音开关"),
+            (MuteStrategy::Gain, "音量归零"),
+        ] {
+            self.strategy.append(Some(strategy.as_str()), desc);
+        }
+
+        self.load();
+
         let this = self.clone();
-        self.push_key_set.connect_clicked(move |_it| {
-            if this.update_hotkey() {
-                this.push_btn.set_active(true);
-                let dialog = MessageDialog::new(
-                    this.window.upgrade().as_ref(),
-                    DialogFlags::MODAL,
-                    MessageType::Info,
-                    ButtonsType::Ok,
-                    "设置成功",
-                );
-                dialog.connect_response(|dialog, _| {
-                    dialog.emit_close();
-                });
-                dialog.run();
-            } else {
-                this.push_btn.set_active(false);
-                let dialog = MessageDialog::new(
-                    this.window.upgrade().as_ref(),
-                    DialogFlags::MODAL,
-                    MessageType::Error,
-                    ButtonsType::Ok,
-                    "设置失败, 快捷键已被占用",
-                );
-                dialog.connect_response(|dialog, _| {
-                    dialog.emit_close();
+        self.strategy.connect_changed(move |it| {
+            if this.updating.get() {
+                return;
+            }
+
+            let strategy = it
+                .get_active_id()
+                .and_then(|it| it.as_str().parse::<MuteStrategy>().ok());
+            let device = this.volume.current_device();
+
+            if let (Some(strategy), Some(device)) = (strategy, device) {
+                update_config(&this.config, |config| {
+                    config.device_mut(&device.id).strategy = strategy;
                 });
-                dialog.run();
             }
         });
 
         let this = self.clone();
-        self.push_btn.connect_state_set(move |_it, enable| {
-            if enable {
-                this.update_hotkey();
-            } else {
-                unregister_hotkey(2);
+        self.device.connect_changed(move |it| {
+            if this.updating.get() {
+                return;
+            }
+
+            let id = it
+                .get_active_id()
+                .map(|it| it.as_str().to_string())
+                .filter(|it| !it.is_empty());
+
+            match this.volume.select_device(id.as_deref()) {
+                Ok(_) => {
+                    *this.selected.borrow_mut() = id.clone();
+                    update_config(&this.config, |config| config.current_mut().device = id);
+                    this.refresh_strategy();
+                }
+                Err(e) => {
+                    let dialog = MessageDialog::new(
+                        this.window.upgrade().as_ref(),
+                        DialogFlags::MODAL,
+                        MessageType::Error,
+                        ButtonsType::Ok,
+                        &format!("切换设备失败: {}", e),
+                    );
+                    dialog.connect_response(|dialog, _| {
+                        dialog.emit_close();
+                    });
+                    dialog.run();
+
+                    this.refresh();
+                }
             }
-            Inhibit(false)
         });
     }
 
-    fn update_hotkey(&self) -> bool {
-        let key_code = self
-            .push_key
-            .get_active_id()
-            .and_then(|it| it.as_str().parse::<u32>().ok())
-            .unwrap();
+    fn refresh(&self) {
+        self.updating.set(true);
+
+        self.device.remove_all();
+        self.device.append(Some(""), "系统默认设备");
+        for device in self.volume.devices().unwrap_or_default() {
+            self.device.append(Some(&device.id), &device.name);
+        }
+
+        let selected = self.selected.borrow().clone().unwrap_or_default();
+        if !self.device.set_active_id(Some(&selected)) {
+            self.device.set_active_id(Some(""));
+        }
 
-        let mod_code = (self.push_mod.ctrl.get_active() as u32) << 1
-            | (self.push_mod.alt.get_active() as u32) << 0
-            | (self.push_mod.shift.get_active() as u32) << 2
-            | (self.push_mod.win.get_active() as u32) << 3
-            | 0x4000;
+        self.updating.set(false);
 
-        unregister_hotkey(2);
-        register_hotkey(mod_code, key_code, 2)
+        self.refresh_strategy();
+    }
+
+    /// 切换到配置中的设备, 设备不在时先跟随系统默认设备, 配置保持不变
+    fn load(&self) {
+        let id = self.config.borrow().current().device;
+
+        if *self.selected.borrow() != id {
+            match self.volume.select_device(id.as_deref()) {
+                Ok(_) => *self.selected.borrow_mut() = id,
+                Err(e) => eprintln!("Select capture device {:?} failed: {:#}", id, e),
+            }
+        }
+
+        self.refresh();
+    }
+
+    /// 显示当前设备的静音方式
+    fn refresh_strategy(&self) {
+        self.updating.set(true);
+
+        match self.volume.current_device() {
+            Some(device) => {
+                let strategy = self.config.borrow().device(&device.id).strategy;
+                self.strategy.set_sensitive(true);
+                self.strategy.set_active_id(Some(strategy.as_str()));
+            }
+            None => {
+                self.strategy.set_sensitive(false);
+            }
+        }
+
+        self.updating.set(false);
     }
 }
 
-impl PushView {
-    pub fn new(builder: &Builder, window: WeakRef<Window>) -> Result<Rc<PushView>> {
-        let push_btn = builder.get_object("push_btn").context("mute btn")?;
-
-        let ctrl = builder.get_object("push_ctrl").context("ctrl")?;
-        let alt = builder.get_object("push_alt").context("alt")?;
-        let shift = builder.get_object("push_shift").context("shift")?;
-        let win = builder.get_object("push_win").context("win")?;
-
-        let push_key: ComboBoxText = builder.get_object("push_key").context("key")?;
-        let push_key_set = builder.get_object("push_hotkey_set").unwrap();
-
-        for (code, desc) in KEY_MAP.iter() {
-            push_key.append(Some(&code.to_string()), desc);
-        }
-        push_key.set_active(Some(0));
-
-        let view = PushView {
-            push_btn,
-            push_mod: ModKeyView {
-                ctrl,
-                alt,
-                shift,
-                win,
-            },
-            push_key,
-            push_key_set,
+impl DeviceView {
+    pub fn new(
+        builder: &Builder,
+        window: WeakRef<Window>,
+        volume: Rc<dyn MicBackend>,
+        config: Rc<RefCell<Config>>,
+    ) -> Result<Rc<DeviceView>> {
+        let device = builder.get_object("device").context("device")?;
+        let strategy = builder.get_object("strategy").context("strategy")?;
+
+        let view = DeviceView {
+            device,
+            strategy,
+            selected: RefCell::new(None),
+            updating: Cell::new(false),
             window,
+            volume,
+            config,
         };
 
         Ok(Rc::new(view))
     }
+}
 
-    pub fn get_key_codes(&self) -> Vec<u32> {
-        let mut codes = Vec::new();
+/// 正在录音的程序列表, 每行可以勾选加入静音范围或者豁免列表, 也可以直接切换这个流的静音
+pub struct StreamsView {
+    list: ListBox,
 
-        if self.push_mod.ctrl.get_active() {
-            codes.push(0x11);
-        }
+    volume: Rc<MuteScope>,
+}
 
-        if self.push_mod.alt.get_active() {
-            codes.push(0x12);
-        }
+pub trait StreamsViewExt {
+    fn refresh(&self);
+}
 
-        if self.push_mod.shift.get_active() {
-            codes.push(0x10);
+impl StreamsViewExt for Rc<StreamsView> {
+    fn refresh(&self) {
+        for child in self.list.get_children() {
+            self.list.remove(&child);
         }
 
-        if self.push_mod.win.get_active() {
-            codes.push(0x5b);
-            codes.push(0x5c);
+        let streams = match self.volume.streams() {
+            Ok(it) => it,
+            Err(e) => {
+                eprintln!("List capture streams failed: {:#}", e);
+                Vec::new()
+            }
+        };
+
+        if streams.is_empty() {
+            let label = Label::new(Some("没有正在录音的程序"));
+            label.set_halign(gtk::Align::Start);
+            self.list.add(&label);
         }
 
-        codes.push(
-            self.push_key
-                .get_active_id()
-                .and_then(|it| it.as_str().parse::<u32>().ok())
-                .unwrap(),
-        );
+        for stream in streams {
+            let row = gtk::Box::new(Orientation::Horizontal, 6);
+
+            let check = CheckButton::with_label(&format!("{} - {}", stream.app, stream.name));
+            check.set_active(self.volume.is_app_selected(&stream.app));
+            let this = self.clone();
+            let app = stream.app.clone();
+            check.connect_toggled(move |it| {
+                this.volume.set_app_selected(&app, it.get_active());
+            });
+            row.pack_start(&check, true, true, 0);
+
+            let exempt = CheckButton::with_label("豁免");
+            exempt.set_active(self.volume.is_app_exempt(&stream.app));
+            let this = self.clone();
+            let app = stream.app.clone();
+            exempt.connect_toggled(move |it| {
+                this.volume.set_app_exempt(&app, it.get_active());
+                // 移出豁免列表的程序按当前状态静音
+                this.volume.sync_streams();
+            });
+            row.pack_start(&exempt, false, false, 0);
+
+            let switch = Switch::new();
+            switch.set_active(stream.muted);
+            let this = self.clone();
+            let id = stream.id;
+            switch.connect_state_set(move |_it, state| {
+                if let Err(e) = this.volume.set_stream_mute(id, state) {
+                    eprintln!("Set mute of capture stream {} failed: {:#}", id, e);
+                }
+
+                Inhibit(false)
+            });
+            row.pack_end(&switch, false, false, 0);
+
+            self.list.add(&row);
+        }
 
-        codes
+        self.list.show_all();
     }
+}
+
+impl StreamsView {
+    pub fn new(builder: &Builder, volume: Rc<MuteScope>) -> Result<Rc<StreamsView>> {
+        let list = builder.get_object("streams").context("streams")?;
 
-    pub fn is_enable(&self) -> bool {
-        self.push_btn.get_active()
+        Ok(Rc::new(StreamsView { list, volume }))
     }
 }
 
 pub struct View {
     pub window: Window,
-    pub mute_view: Rc<MuteView>,
-    pub push_view: Rc<PushView>,
-
+    pub mute_view: Rc<HotkeyRow>,
+    pub push_view: Rc<HotkeyRow>,
+    pub cough_view: Rc<HotkeyRow>,
+    pub gain_view: Rc<HotkeyRow>,
+    pub profile_view: Rc<HotkeyRow>,
+    pub device_view: Rc<DeviceView>,
+    pub streams_view: Rc<StreamsView>,
+
+    pub profile: ComboBoxText,
+    pub profile_new: Button,
     pub switch: Switch,
     pub status: Label,
-
-    volume: Rc<Volume>,
+    pub scope: ComboBoxText,
+    pub gain: Scale,
+    pub gain_db: Label,
+    // 按设备状态刷新开关时会触发 state-set 信号, 此时不应再去修改设备
+    syncing: Cell<bool>,
+    ptt: RefCell<PttMachine>,
+    // 按住静音时记下的原来的状态
+    cough: RefCell<Option<MuteSnapshot>>,
+
+    volume: Rc<MuteScope>,
+    config: Rc<RefCell<Config>>,
+    config_monitor: RefCell<Option<FileMonitor>>,
 }
 
 pub trait ViewExt {
@@ -476,6 +662,46 @@ pub trait ViewExt {
     fn unmute(&self);
 
     fn toggle(&self);
+
+    fn sync_state(&self);
+
+    fn update_status(&self, state: MuteState);
+
+    fn sync_gain(&self);
+
+    fn step_gain(&self, delta: f32);
+
+    fn on_volume_event(&self, event: VolumeEvent);
+
+    fn on_hotkey(&self, id: i32);
+
+    fn on_hotkey_release(&self, id: i32);
+
+    fn on_hotkey_failed(&self, id: i32, reason: &str);
+
+    fn on_push_to_talk(&self, pressed: bool);
+
+    fn apply_ptt(&self, action: Option<Action>);
+
+    fn on_push_to_mute(&self, pressed: bool);
+
+    fn register_hotkeys(&self);
+
+    fn watch_config(&self);
+
+    fn reload_config(&self);
+
+    fn apply_config(&self);
+
+    fn profiles(&self) -> (Vec<String>, String);
+
+    fn refresh_profiles(&self);
+
+    fn switch_profile(&self, name: &str);
+
+    fn next_profile(&self);
+
+    fn new_profile(&self);
 }
 
 impl ViewExt for Rc<View> {
@@ -483,11 +709,37 @@ impl ViewExt for Rc<View> {
         self.window.set_application(Some(app));
         self.window.set_title("Mute Assistant");
 
-        self.mute_view.init(app);
+        self.mute_view.init();
         self.push_view.init();
+        self.cough_view.init();
+        self.gain_view.init();
+        self.profile_view.init();
+        self.device_view.init();
+        self.streams_view.refresh();
+        self.refresh_profiles();
+
+        let this = self.clone();
+        self.profile.connect_changed(move |it| {
+            if this.syncing.get() {
+                return;
+            }
+
+            if let Some(name) = it.get_active_id() {
+                this.switch_profile(name.as_str());
+            }
+        });
+
+        let this = self.clone();
+        self.profile_new.connect_clicked(move |_it| {
+            this.new_profile();
+        });
 
         let this = self.clone();
         self.switch.connect_state_set(move |_it, state| {
+            if this.syncing.get() {
+                return Inhibit(false);
+            }
+
             if state {
                 this.mute();
             } else {
@@ -497,6 +749,43 @@ impl ViewExt for Rc<View> {
             Inhibit(false)
         });
 
+        let this = self.clone();
+        self.gain.connect_value_changed(move |it| {
+            if this.syncing.get() {
+                return;
+            }
+
+            if let Err(e) = this.volume.set_gain(it.get_value() as f32 / 100.0) {
+                eprintln!("Set capture gain failed: {:#}", e);
+            }
+            this.sync_gain();
+        });
+
+        for &(mode, desc) in &[
+            (ScopeMode::Device, "当前设备"),
+            (ScopeMode::AllInputs, "所有输入设备"),
+            (ScopeMode::Apps, "选中的程序"),
+            (ScopeMode::Exempt, "豁免以外的程序"),
+        ] {
+            self.scope.append(Some(mode.as_str()), desc);
+        }
+        self.scope.set_active_id(Some(self.volume.mode().as_str()));
+
+        let this = self.clone();
+        self.scope.connect_changed(move |it| {
+            if this.syncing.get() {
+                return;
+            }
+
+            let mode = it
+                .get_active_id()
+                .and_then(|it| it.as_str().parse::<ScopeMode>().ok())
+                .unwrap_or_default();
+
+            this.volume.set_mode(mode);
+            this.sync_state();
+        });
+
         self.window.connect_delete_event(|it, _event| {
             it.hide_on_delete();
 
@@ -514,39 +803,395 @@ impl ViewExt for Rc<View> {
 
     fn mute(&self) {
         self.volume.mute();
-        self.status.set_text("静音");
-        self.switch.set_active(true);
+        self.sync_state();
     }
 
     fn unmute(&self) {
         self.volume.unmute();
-        self.status.set_text("正常");
-        self.switch.set_active(false);
+        self.sync_state();
     }
 
     fn toggle(&self) {
         self.volume.toggle();
-        let is_mute = self.volume.is_mute();
-        self.switch.set_active(is_mute);
+        self.sync_state();
+    }
+
+    /// 以设备的实际状态为准刷新开关, 状态文字和托盘
+    fn sync_state(&self) {
+        let state = self.volume.mute_state();
+
+        self.syncing.set(true);
+        self.switch.set_active(state == MuteState::Muted);
+        self.syncing.set(false);
+
+        self.update_status(state);
+        self.sync_gain();
+    }
+
+    fn update_status(&self, state: MuteState) {
+        update_tray(state == MuteState::Muted);
+
+        let text = match state {
+            MuteState::Muted => "静音",
+            MuteState::Partial => "部分静音",
+            MuteState::Live => "正常",
+        };
+
+        let device = match self.volume.mode() {
+            ScopeMode::Device => self.volume.current_device().map(|it| it.name),
+            ScopeMode::AllInputs => Some("所有输入设备".to_string()),
+            ScopeMode::Apps => Some("选中的程序".to_string()),
+            ScopeMode::Exempt => Some("豁免以外的程序".to_string()),
+        };
+
+        match device {
+            Some(device) => self.status.set_text(&format!("{} ({})", text, device)),
+            None => self.status.set_text(text),
+        }
+    }
+
+    fn sync_gain(&self) {
+        self.syncing.set(true);
+        match (self.volume.gain(), self.volume.gain_db()) {
+            (Ok(gain), Ok(db)) => {
+                self.gain.set_sensitive(true);
+                self.gain.set_value((gain * 100.0) as f64);
+                self.gain_db.set_text(&format!("{:.1} dB", db));
+            }
+            _ => {
+                // 设备没有音量控制
+                self.gain.set_sensitive(false);
+                self.gain_db.set_text("-");
+            }
+        }
+        self.syncing.set(false);
+    }
+
+    fn step_gain(&self, delta: f32) {
+        let result = self
+            .volume
+            .gain()
+            .and_then(|gain| self.volume.set_gain(gain + delta));
+        if let Err(e) = result {
+            eprintln!("Set capture gain failed: {:#}", e);
+        }
+
+        self.sync_gain();
+    }
+
+    fn on_volume_event(&self, event: VolumeEvent) {
+        match event {
+            VolumeEvent::DefaultDevice => {
+                if let Err(e) = self.volume.rebind() {
+                    eprintln!("Rebind to default capture device failed: {:#}", e);
+                }
+
+                self.device_view.refresh();
+                self.sync_state();
+            }
+            VolumeEvent::Devices => {
+                self.device_view.refresh();
+            }
+            VolumeEvent::Mute => {
+                self.sync_state();
+            }
+            VolumeEvent::Streams => {
+                self.volume.sync_streams();
+                self.streams_view.refresh();
+                self.sync_state();
+            }
+        }
+    }
+
+    /// 全局快捷键按下, `id` 为注册时使用的 id
+    fn on_hotkey(&self, id: i32) {
+        match id {
+            MUTE_HOTKEY => self.toggle(),
+            PUSH_HOTKEY => self.on_push_to_talk(true),
+            GAIN_UP_HOTKEY => self.step_gain(GAIN_STEP),
+            GAIN_DOWN_HOTKEY => self.step_gain(-GAIN_STEP),
+            PROFILE_HOTKEY => self.next_profile(),
+            COUGH_HOTKEY => self.on_push_to_mute(true),
+            _ => {}
+        }
+    }
+
+    /// 全局快捷键松开, 只有按住说话和按住静音需要处理
+    fn on_hotkey_release(&self, id: i32) {
+        match id {
+            PUSH_HOTKEY => self.on_push_to_talk(false),
+            COUGH_HOTKEY => self.on_push_to_mute(false),
+            _ => {}
+        }
+    }
+
+    /// 快捷键在注册之后才绑定失败, 提示显示在对应的录制按钮旁
+    fn on_hotkey_failed(&self, id: i32, reason: &str) {
+        let rows = [
+            &self.mute_view,
+            &self.push_view,
+            &self.cough_view,
+            &self.gain_view,
+            &self.profile_view,
+        ];
+        let hotkey = bound_hotkeys(&self.config.borrow())
+            .into_iter()
+            .find(|it| it.0 == id)
+            .map(|it| it.2);
+        let text = format!("{} {}", hotkey_text(hotkey), reason);
+
+        if !rows.iter().any(|row| row.warn(id, text.trim())) {
+            eprintln!("Hotkey {} failed: {}", id, reason);
+        }
+    }
+
+    /// 按下和松开交给状态机, 松开后需要防抖时等到期限再检查一次
+    fn on_push_to_talk(&self, pressed: bool) {
+        let debounce = self.config.borrow().current().hotkeys.push_debounce;
+        let now = glib::get_monotonic_time() as u64 / 1000;
+
+        let action = {
+            let mut ptt = self.ptt.borrow_mut();
+            ptt.set_debounce(debounce);
+            if pressed {
+                ptt.press(now)
+            } else {
+                ptt.release(now)
+            }
+        };
+        self.apply_ptt(action);
+
+        let deadline = self.ptt.borrow().deadline();
+        if let Some(deadline) = deadline {
+            let this = self.clone();
+            gtk::timeout_add(deadline.saturating_sub(now) as u32, move || {
+                let now = glib::get_monotonic_time() as u64 / 1000;
+                let action = this.ptt.borrow_mut().poll(now);
+                this.apply_ptt(action);
+                glib::Continue(false)
+            });
+        }
+    }
+
+    fn apply_ptt(&self, action: Option<Action>) {
+        match action {
+            Some(Action::Unmute) => self.unmute(),
+            Some(Action::Mute) => self.mute(),
+            None => {}
+        }
+    }
+
+    /// 按下时记下当前状态再静音, 松开时恢复, 按住期间切换过静音也以按下时为准
+    fn on_push_to_mute(&self, pressed: bool) {
+        if pressed {
+            if self.cough.borrow().is_some() {
+                return;
+            }
+            *self.cough.borrow_mut() = Some(self.volume.snapshot());
+            self.mute();
+        } else if let Some(snapshot) = self.cough.borrow_mut().take() {
+            self.volume.restore(&snapshot);
+            self.sync_state();
+        }
+    }
+
+    /// 按开关状态注册或者注销快捷键, 需要在窗口创建之后调用
+    fn register_hotkeys(&self) {
+        // 各平台判断按住说话是否松开时使用
+        unsafe { RELEASE_MODE = self.config.borrow().current().hotkeys.push_release };
+
+        for (row, name) in &[
+            (&self.mute_view, "mute"),
+            (&self.push_view, "push-to-talk"),
+            (&self.cough_view, "push-to-mute"),
+            (&self.gain_view, "gain"),
+            (&self.profile_view, "profile"),
+        ] {
+            if !row.apply() {
+                eprintln!("Register {} hotkey failed", name);
+            }
+        }
+    }
+
+    fn watch_config(&self) {
+        let this = self.clone();
+        match Config::watch(move || this.reload_config()) {
+            Ok(monitor) => *self.config_monitor.borrow_mut() = Some(monitor),
+            Err(e) => eprintln!("Watch config file failed: {:#}", e),
+        }
+    }
+
+    /// 配置文件被外部修改后重新应用所有设置
+    fn reload_config(&self) {
+        let config = match Config::load() {
+            Ok(it) => it,
+            Err(e) => {
+                eprintln!("Reload config failed: {:#}", e);
+                return;
+            }
+        };
+
+        // 自己保存配置时也会收到通知, 内容相同时不需要处理
+        if *self.config.borrow() == config {
+            return;
+        }
+        *self.config.borrow_mut() = config;
+
+        self.apply_config();
+    }
+
+    /// 让界面, 快捷键和设备都和当前配置一致
+    fn apply_config(&self) {
+        self.mute_view.load();
+        self.push_view.load();
+        self.cough_view.load();
+        self.gain_view.load();
+        self.profile_view.load();
+        self.register_hotkeys();
+
+        self.device_view.load();
+
+        self.syncing.set(true);
+        self.scope.set_active_id(Some(self.volume.mode().as_str()));
+        self.syncing.set(false);
+
+        self.refresh_profiles();
+        self.streams_view.refresh();
+        self.volume.sync_streams();
+        self.sync_state();
+    }
+
+    /// 所有方案的名字和当前方案
+    fn profiles(&self) -> (Vec<String>, String) {
+        let config = self.config.borrow();
+
+        (config.profiles.keys().cloned().collect(), config.profile.clone())
+    }
+
+    fn refresh_profiles(&self) {
+        let (names, current) = self.profiles();
+
+        self.syncing.set(true);
+        self.profile.remove_all();
+        for name in &names {
+            self.profile.append(Some(name), name);
+        }
+        self.profile.set_active_id(Some(&current));
+        self.syncing.set(false);
+    }
+
+    fn switch_profile(&self, name: &str) {
+        let result = self.config.borrow_mut().use_profile(name);
+        if let Err(e) = result {
+            eprintln!("Switch profile failed: {:#}", e);
+            self.refresh_profiles();
+            return;
+        }
+
+        if let Err(e) = self.config.borrow().save() {
+            eprintln!("Save config failed: {:#}", e);
+        }
+        self.apply_config();
+    }
+
+    fn next_profile(&self) {
+        let name = self.config.borrow().next_profile();
+        self.switch_profile(&name);
+    }
+
+    /// 输入名字, 以当前方案为模板新建方案并切换过去
+    fn new_profile(&self) {
+        let dialog = Dialog::new_with_buttons(
+            Some("新建配置方案"),
+            Some(&self.window),
+            DialogFlags::MODAL,
+            &[("取消", ResponseType::Cancel), ("确定", ResponseType::Ok)],
+        );
+        let entry = Entry::new();
+        entry.set_activates_default(true);
+        dialog.get_content_area().add(&entry);
+        dialog.set_default_response(ResponseType::Ok);
+        dialog.show_all();
+
+        let response = dialog.run();
+        let name = entry.get_text().map(|it| it.to_string()).unwrap_or_default();
+        dialog.destroy();
+
+        if response != ResponseType::Ok {
+            return;
+        }
+
+        let result = self.config.borrow_mut().add_profile(name.trim());
+        match result {
+            Ok(_) => self.switch_profile(name.trim()),
+            Err(e) => {
+                let dialog = MessageDialog::new(
+                    Some(&self.window),
+                    DialogFlags::MODAL,
+                    MessageType::Error,
+                    ButtonsType::Ok,
+                    &format!("新建配置方案失败: {}", e),
+                );
+                dialog.connect_response(|dialog, _| {
+                    dialog.emit_close();
+                });
+                dialog.run();
+            }
+        }
     }
 }
 
 impl View {
-    pub fn new(builder: &Builder, volume: Rc<Volume>) -> Result<Rc<View>> {
+    pub fn new(
+        builder: &Builder,
+        volume: Rc<MuteScope>,
+        config: Rc<RefCell<Config>>,
+    ) -> Result<Rc<View>> {
         let window: Window = builder.get_object("settings").context("main window")?;
         let switch: Switch = builder.get_object("switch").unwrap();
         let status = builder.get_object("status").unwrap();
-
-        let mute_view = MuteView::new(builder, window.downgrade(), volume.clone())?;
-        let push_view = PushView::new(builder, window.downgrade())?;
+        let scope = builder.get_object("scope").context("scope")?;
+        let profile = builder.get_object("profile").context("profile")?;
+        let profile_new = builder.get_object("profile_new").context("profile new")?;
+        let gain = builder.get_object("gain").context("gain")?;
+        let gain_db = builder.get_object("gain_db").context("gain db")?;
+
+        let mute_view =
+            HotkeyRow::new(builder, HotkeySlot::Mute, window.downgrade(), config.clone())?;
+        let push_view =
+            HotkeyRow::new(builder, HotkeySlot::Push, window.downgrade(), config.clone())?;
+        let cough_view =
+            HotkeyRow::new(builder, HotkeySlot::Cough, window.downgrade(), config.clone())?;
+        let gain_view =
+            HotkeyRow::new(builder, HotkeySlot::Gain, window.downgrade(), config.clone())?;
+        let profile_view =
+            HotkeyRow::new(builder, HotkeySlot::Profile, window.downgrade(), config.clone())?;
+        let device_view =
+            DeviceView::new(builder, window.downgrade(), volume.clone(), config.clone())?;
+        let streams_view = StreamsView::new(builder, volume.clone())?;
 
         let view = View {
             window,
             mute_view,
             push_view,
+            cough_view,
+            gain_view,
+            profile_view,
+            device_view,
+            streams_view,
+            profile,
+            profile_new,
             switch,
             status,
+            scope,
+            gain,
+            gain_db,
+            syncing: Cell::new(false),
+            ptt: RefCell::new(PttMachine::new(0)),
+            cough: RefCell::new(None),
             volume,
+            config,
+            config_monitor: RefCell::new(None),
         };
         Ok(Rc::new(view))
     }
@@ -0,0 +1,202 @@
+use gdk::ModifierType;
+use gtk::prelude::*;
+use gtk::{Button, Label};
+
+use crate::hotkey::{Hotkey, Key, Modifiers};
+use std::cell::Cell;
+use std::rc::Rc;
+
+// X11 keysym
+const KEY_ESCAPE: u32 = 0xFF1B;
+const KEY_BACKSPACE: u32 = 0xFF08;
+
+/// 点击后录制下一次按下的组合键, 显示为 `Ctrl+Alt+M` 这样的字符串
+pub struct HotkeyCapture {
+    button: Button,
+    // 保留组合或者和其他快捷键冲突时的提示
+    warning: Label,
+
+    hotkey: Cell<Option<Hotkey>>,
+    capturing: Cell<bool>,
+
+    // 检查录制到的组合键, 有问题时返回提示文字
+    check: Box<dyn Fn(Hotkey) -> Option<String>>,
+}
+
+impl HotkeyCapture {
+    pub fn new(
+        button: Button,
+        warning: Label,
+        check: impl Fn(Hotkey) -> Option<String> + 'static,
+    ) -> Rc<HotkeyCapture> {
+        let capture = Rc::new(HotkeyCapture {
+            button,
+            warning,
+            hotkey: Cell::new(None),
+            capturing: Cell::new(false),
+            check: Box::new(check),
+        });
+        capture.init();
+
+        capture
+    }
+
+    fn init(self: &Rc<Self>) {
+        let this = self.clone();
+        self.button.connect_clicked(move |it| {
+            if this.capturing.get() {
+                return;
+            }
+
+            this.capturing.set(true);
+            this.warning.set_text("");
+            it.set_label("请按下快捷键...");
+            it.grab_focus();
+        });
+
+        let this = self.clone();
+        self.button.connect_key_press_event(move |_it, event| {
+            if !this.capturing.get() {
+                return Inhibit(false);
+            }
+
+            this.on_key_press(event);
+            Inhibit(true)
+        });
+
+        // 侧键没有 keysym, 只能从鼠标事件里拿到
+        let this = self.clone();
+        self.button.connect_button_press_event(move |_it, event| {
+            if !this.capturing.get() {
+                return Inhibit(false);
+            }
+
+            let key = match event.get_button() {
+                8 => Key::Mouse4,
+                9 => Key::Mouse5,
+                _ => return Inhibit(false),
+            };
+            this.finish(Some(Hotkey::new(modifiers(event.get_state()), key)));
+            Inhibit(true)
+        });
+
+        let this = self.clone();
+        self.button.connect_focus_out_event(move |_it, _event| {
+            if this.capturing.get() {
+                this.cancel();
+            }
+
+            Inhibit(false)
+        });
+    }
+
+    fn on_key_press(&self, event: &gdk::EventKey) {
+        let keyval = base_keyval(event);
+        // 只按下了修饰键, 继续等待
+        if is_modifier(keyval) {
+            return;
+        }
+
+        let modifiers = modifiers(event.get_state());
+        if modifiers == Modifiers::default() {
+            match keyval {
+                KEY_ESCAPE => return self.cancel(),
+                KEY_BACKSPACE => return self.finish(None),
+                _ => {}
+            }
+        }
+
+        match Key::from_keysym(keyval) {
+            Some(key) => self.finish(Some(Hotkey::new(modifiers, key))),
+            None => {
+                let name = gdk::keyval_name(keyval).map(|it| it.to_string());
+                self.warn(&format!("不支持的按键: {}", name.unwrap_or_default()));
+            }
+        }
+    }
+
+    fn finish(&self, hotkey: Option<Hotkey>) {
+        self.set_hotkey(hotkey);
+
+        if let Some(hotkey) = hotkey {
+            if let Some(warning) = (self.check)(hotkey) {
+                self.warn(&warning);
+            }
+        }
+    }
+
+    /// 放弃录制, 恢复原来的快捷键
+    fn cancel(&self) {
+        self.set_hotkey(self.hotkey.get());
+    }
+
+    pub fn hotkey(&self) -> Option<Hotkey> {
+        self.hotkey.get()
+    }
+
+    pub fn set_hotkey(&self, hotkey: Option<Hotkey>) {
+        self.capturing.set(false);
+        self.hotkey.set(hotkey);
+        self.warning.set_text("");
+
+        match hotkey {
+            Some(hotkey) => self.button.set_label(&hotkey.to_string()),
+            None => self.button.set_label("未设置"),
+        }
+    }
+
+    pub fn warn(&self, text: &str) {
+        self.warning.set_markup(&format!(
+            "<span foreground=\"red\">{}</span>",
+            glib::markup_escape_text(text)
+        ));
+    }
+}
+
+fn modifiers(state: ModifierType) -> Modifiers {
+    Modifiers {
+        ctrl: state.contains(ModifierType::CONTROL_MASK),
+        alt: state.contains(ModifierType::MOD1_MASK),
+        shift: state.contains(ModifierType::SHIFT_MASK),
+        win: state.intersects(ModifierType::SUPER_MASK | ModifierType::MOD4_MASK),
+    }
+}
+
+/// Shift, Control, Meta, Alt, Super 的左右两个键, 以及 AltGr.
+/// Caps_Lock 等锁定键也在这一段 keysym 里, 但可以作为快捷键的按键
+fn is_modifier(keyval: u32) -> bool {
+    const MODIFIERS: [u32; 11] = [
+        0xFFE1, 0xFFE2, // Shift_L, Shift_R
+        0xFFE3, 0xFFE4, // Control_L, Control_R
+        0xFFE7, 0xFFE8, // Meta_L, Meta_R
+        0xFFE9, 0xFFEA, // Alt_L, Alt_R
+        0xFFEB, 0xFFEC, // Super_L, Super_R
+        0xFE03, // ISO_Level3_Shift
+    ];
+
+    MODIFIERS.contains(&keyval)
+}
+
+/// 去掉修饰键影响后的 keysym, 这样 Shift+1 得到的是 1 而不是 !, 保留 NumLock 等状态
+fn base_keyval(event: &gdk::EventKey) -> u32 {
+    let state = event.get_state()
+        - (ModifierType::SHIFT_MASK
+            | ModifierType::CONTROL_MASK
+            | ModifierType::MOD1_MASK
+            | ModifierType::SUPER_MASK
+            | ModifierType::MOD4_MASK);
+
+    let keyval = gdk::Display::get_default()
+        .and_then(|display| gdk::Keymap::get_for_display(&display))
+        .and_then(|keymap| {
+            keymap.translate_keyboard_state(
+                event.get_hardware_keycode() as u32,
+                state,
+                event.get_group() as i32,
+            )
+        })
+        .map(|(keyval, ..)| keyval)
+        .unwrap_or_else(|| event.get_keyval());
+
+    gdk::keyval_to_lower(keyval)
+}
@@ -1,109 +1,298 @@
+use std::rc::Rc;
+use std::str::FromStr;
+
 use anyhow::Result;
 
-use winapi::um::combaseapi::*;
-use winapi::um::endpointvolume::*;
-use winapi::um::mmdeviceapi::*;
+#[cfg(all(target_os = "linux", feature = "alsa"))]
+mod alsa;
+mod fallback;
+#[cfg(all(target_os = "linux", feature = "jack"))]
+mod jack;
+mod mock;
+#[cfg(all(target_os = "linux", feature = "pipewire"))]
+mod pipewire;
+#[cfg(all(target_os = "linux", feature = "pulse"))]
+mod pulse;
+mod scope;
+#[cfg(target_os = "windows")]
+mod wasapi;
 
-use winapi::shared::winerror::FAILED;
-use winapi::um::objbase::CoInitialize;
-use winapi::Interface;
+#[cfg(all(target_os = "linux", feature = "alsa"))]
+pub use self::alsa::AlsaVolume;
+pub use fallback::GainFallback;
+#[cfg(all(target_os = "linux", feature = "jack"))]
+pub use self::jack::JackVolume;
+pub use mock::MockVolume;
+#[cfg(all(target_os = "linux", feature = "pipewire"))]
+pub use self::pipewire::PipeWireVolume;
+#[cfg(all(target_os = "linux", feature = "pulse"))]
+pub use pulse::PulseVolume;
+pub use scope::{MuteScope, MuteSnapshot, ScopeMode};
+#[cfg(target_os = "windows")]
+pub use wasapi::Volume;
 
-use std::ptr;
-use std::ptr::NonNull;
+/// 一个输入设备, `id` 在设备重新插拔后保持不变, `name` 用于显示
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureDevice {
+    pub id: String,
+    pub name: String,
+}
 
-macro_rules! check_result {
-    ( $result:ident, $reason:expr ) => {
-        if FAILED($result) {
-            return Err(anyhow::anyhow!($reason));
-        }
-    };
+/// 一个程序的录音流, 例如 PulseAudio 的 source-output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureStream {
+    pub id: u32,
+    /// 程序名称, 即 `application.name`
+    pub app: String,
+    /// 录音流的名称, 同一个程序可能有多个录音流
+    pub name: String,
+    pub muted: bool,
 }
 
-#[allow(dead_code)]
-pub struct Volume {
-    enumerator: NonNull<IMMDeviceEnumerator>,
+/// 静音状态, 同时操作多个设备时可能只有一部分被静音
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MuteState {
+    Muted,
+    Partial,
+    Live,
+}
 
-    default_device: NonNull<IMMDevice>,
+/// 后端产生的事件, 可能来自其他线程, 通过 glib channel 送回主线程处理
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VolumeEvent {
+    /// 系统默认输入设备改变
+    DefaultDevice,
+    /// 输入设备被插入, 拔出或者启用/禁用
+    Devices,
+    /// 静音状态或音量可能被其他程序或系统混音器修改, 需要重新读取
+    Mute,
+    /// 有程序开始或停止录音, 或者录音流的静音状态改变
+    Streams,
+}
+
+/// 设备没有给出 dB 范围时 (软件音量) 使用的范围, 低于下限视为无声
+const SOFT_GAIN_RANGE_DB: (f32, f32) = (-60.0, 0.0);
+
+fn amplitude_to_db(amplitude: f32) -> f32 {
+    if amplitude <= 0.0 {
+        return SOFT_GAIN_RANGE_DB.0;
+    }
 
-    volume_endpoint: NonNull<IAudioEndpointVolume>,
+    (20.0 * amplitude.log10()).max(SOFT_GAIN_RANGE_DB.0)
 }
 
-impl Volume {
-    pub fn new() -> Result<Self> {
-        let r = unsafe { CoInitialize(ptr::null_mut()) };
-        check_result!(r, "CoInitialize failed");
-
-        let mut enumerator = ptr::null_mut();
-        let r = unsafe {
-            CoCreateInstance(
-                &CLSID_MMDeviceEnumerator,
-                ptr::null_mut(),
-                CLSCTX_ALL,
-                &IMMDeviceEnumerator::uuidof(),
-                &mut enumerator,
-            )
-        };
-        check_result!(r, "Create IMMDeviceEnumerator instance failed");
-        let enumerator: NonNull<IMMDeviceEnumerator> =
-            NonNull::new(enumerator as *mut IMMDeviceEnumerator).unwrap();
-
-        let mut default_device = ptr::null_mut();
-        let r = unsafe {
-            enumerator
-                .as_ref()
-                .GetDefaultAudioEndpoint(1, 0, &mut default_device)
-        };
-        check_result!(r, "GetDefaultAudioEndpoint failed");
-        let default_device: NonNull<IMMDevice> = NonNull::new(default_device).unwrap();
-
-        let mut volume_endpoint = ptr::null_mut();
-        let r = unsafe {
-            default_device.as_ref().Activate(
-                &IAudioEndpointVolume::uuidof(),
-                CLSCTX_ALL,
-                ptr::null_mut(),
-                &mut volume_endpoint,
-            )
-        };
-        check_result!(r, "Create IAudioEndpointVolume instance failed");
-        let volume_endpoint = NonNull::new(volume_endpoint as *mut IAudioEndpointVolume).unwrap();
-
-        Ok(Volume {
-            enumerator,
-            default_device,
-            volume_endpoint,
-        })
+fn db_to_amplitude(db: f32) -> f32 {
+    if db <= SOFT_GAIN_RANGE_DB.0 {
+        return 0.0;
     }
 
-    pub fn mute(&self) -> bool {
-        let r = unsafe { self.volume_endpoint.as_ref().SetMute(1, ptr::null_mut()) };
-        !FAILED(r)
+    10f32.powf(db / 20.0)
+}
+
+/// 麦克风控制后端, 不同平台/音频系统各自实现
+pub trait MicBackend {
+    fn mute(&self) -> bool;
+
+    fn unmute(&self) -> bool;
+
+    fn toggle(&self);
+
+    fn is_mute(&self) -> bool;
+
+    fn mute_state(&self) -> MuteState {
+        if self.is_mute() {
+            MuteState::Muted
+        } else {
+            MuteState::Live
+        }
     }
 
-    pub fn unmute(&self) -> bool {
-        let r = unsafe { self.volume_endpoint.as_ref().SetMute(0, ptr::null_mut()) };
-        !FAILED(r)
+    /// 列出所有可用的输入设备
+    fn devices(&self) -> Result<Vec<CaptureDevice>>;
+
+    /// 当前操作的设备
+    fn current_device(&self) -> Option<CaptureDevice>;
+
+    /// 固定操作某个设备, `None` 表示使用系统默认设备
+    fn select_device(&self, id: Option<&str>) -> Result<()>;
+
+    /// 修改指定设备的静音状态, 不影响当前操作的设备
+    fn set_device_mute(&self, id: &str, mute: bool) -> Result<()>;
+
+    fn device_mute(&self, id: &str) -> Result<bool>;
+
+    /// 当前设备的输入音量, 范围 0.0 ~ 1.0, 与系统音量设置里的滑块一致
+    fn gain(&self) -> Result<f32>;
+
+    fn set_gain(&self, gain: f32) -> Result<()>;
+
+    fn gain_db(&self) -> Result<f32>;
+
+    fn set_gain_db(&self, db: f32) -> Result<()>;
+
+    /// 设备支持的 dB 范围 (最小值, 最大值)
+    fn gain_range_db(&self) -> Result<(f32, f32)>;
+
+    /// 正在录音的程序, 不支持按程序静音的后端返回空列表
+    fn streams(&self) -> Result<Vec<CaptureStream>> {
+        Ok(Vec::new())
+    }
+
+    fn set_stream_mute(&self, _id: u32, _mute: bool) -> Result<()> {
+        Err(anyhow::anyhow!("Per-application mute is not supported by this backend"))
+    }
+
+    /// 订阅设备变化等事件, 不支持通知的后端什么也不做
+    fn subscribe(&self, _sender: glib::Sender<VolumeEvent>) -> Result<()> {
+        Ok(())
     }
 
-    pub fn toggle(&self) {
-        let mut m = 0;
+    /// 收到 `DefaultDevice` 后调用, 没有固定设备时切换到新的默认设备
+    fn rebind(&self) -> Result<()> {
+        Ok(())
+    }
+}
 
-        unsafe {
-            self.volume_endpoint.as_ref().GetMute(&mut m);
-            dbg!(m);
-            dbg!((m == 0) as i32);
-            self.volume_endpoint
-                .as_ref()
-                .SetMute((m == 0) as i32, ptr::null_mut());
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    #[cfg(target_os = "windows")]
+    Wasapi,
+    #[cfg(all(target_os = "linux", feature = "pulse"))]
+    Pulse,
+    #[cfg(all(target_os = "linux", feature = "pipewire"))]
+    PipeWire,
+    #[cfg(all(target_os = "linux", feature = "alsa"))]
+    Alsa,
+    #[cfg(all(target_os = "linux", feature = "jack"))]
+    Jack,
+    Mock,
+}
+
+impl BackendKind {
+    pub fn platform_default() -> BackendKind {
+        #[cfg(target_os = "windows")]
+        return BackendKind::Wasapi;
+
+        #[cfg(all(target_os = "linux", feature = "pipewire"))]
+        return BackendKind::PipeWire;
+
+        #[cfg(all(target_os = "linux", feature = "pulse"))]
+        return BackendKind::Pulse;
+
+        #[cfg(all(target_os = "linux", feature = "alsa"))]
+        return BackendKind::Alsa;
+
+        #[allow(unreachable_code)]
+        BackendKind::Mock
+    }
+}
+
+impl FromStr for BackendKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            #[cfg(target_os = "windows")]
+            "wasapi" => Ok(BackendKind::Wasapi),
+            #[cfg(all(target_os = "linux", feature = "pulse"))]
+            "pulse" => Ok(BackendKind::Pulse),
+            #[cfg(all(target_os = "linux", feature = "pipewire"))]
+            "pipewire" => Ok(BackendKind::PipeWire),
+            #[cfg(all(target_os = "linux", feature = "alsa"))]
+            "alsa" => Ok(BackendKind::Alsa),
+            #[cfg(all(target_os = "linux", feature = "jack"))]
+            "jack" => Ok(BackendKind::Jack),
+            "mock" => Ok(BackendKind::Mock),
+            _ => Err(anyhow::anyhow!("unknown backend: {}", s)),
         }
     }
+}
+
+/// 通过 `MUTE_BACKEND` 环境变量选择后端, 未设置时使用平台默认后端
+pub fn new_backend() -> Result<Rc<dyn MicBackend>> {
+    let kind = match std::env::var("MUTE_BACKEND") {
+        Ok(name) => name.parse()?,
+        Err(_) => BackendKind::platform_default(),
+    };
+
+    open_backend(kind)
+}
+
+pub fn open_backend(kind: BackendKind) -> Result<Rc<dyn MicBackend>> {
+    match kind {
+        #[cfg(target_os = "windows")]
+        BackendKind::Wasapi => Ok(Rc::new(Volume::new()?)),
+        #[cfg(all(target_os = "linux", feature = "pulse"))]
+        BackendKind::Pulse => Ok(Rc::new(PulseVolume::new()?)),
+        #[cfg(all(target_os = "linux", feature = "pipewire"))]
+        BackendKind::PipeWire => Ok(Rc::new(PipeWireVolume::new()?)),
+        #[cfg(all(target_os = "linux", feature = "alsa"))]
+        BackendKind::Alsa => Ok(Rc::new(AlsaVolume::new()?)),
+        #[cfg(all(target_os = "linux", feature = "jack"))]
+        BackendKind::Jack => Ok(Rc::new(JackVolume::new()?)),
+        BackendKind::Mock => Ok(Rc::new(MockVolume::new())),
+    }
+}
+
+/// 各个后端共用的检查, 真实后端的测试需要声音服务器或者声卡, 默认忽略
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// 检查当前设备的静音开关, 结束后恢复原来的状态
+    pub fn check_mute(backend: &dyn MicBackend) {
+        let device = backend.current_device().expect("no current device");
+        assert!(backend.devices().unwrap().contains(&device));
+
+        let muted = backend.is_mute();
+
+        assert!(backend.mute());
+        assert!(backend.is_mute());
+        assert_eq!(backend.mute_state(), MuteState::Muted);
+        assert!(backend.device_mute(&device.id).unwrap());
+
+        backend.toggle();
+        assert!(!backend.is_mute());
+        assert_eq!(backend.mute_state(), MuteState::Live);
+
+        backend.toggle();
+        assert!(backend.is_mute());
+
+        assert!(backend.unmute());
+        assert!(!backend.is_mute());
 
-    pub fn is_mute(&self) -> bool {
-        let mut m = 0;
-        unsafe {
-            self.volume_endpoint.as_ref().GetMute(&mut m);
+        backend.set_device_mute(&device.id, true).unwrap();
+        assert!(backend.is_mute());
+
+        backend.set_device_mute(&device.id, muted).unwrap();
+        assert_eq!(backend.is_mute(), muted);
+    }
+
+    /// 检查当前设备的音量, 各后端换算方式不同, 只要求大致相等
+    pub fn check_gain(backend: &dyn MicBackend) {
+        let gain = backend.gain().unwrap();
+
+        for &value in &[0.25, 0.5, 1.0] {
+            backend.set_gain(value).unwrap();
+            let actual = backend.gain().unwrap();
+            assert!((actual - value).abs() < 0.05, "set {}, got {}", value, actual);
         }
 
-        m == 1
+        let (min, max) = backend.gain_range_db().unwrap();
+        assert!(min < max);
+
+        backend.set_gain(gain).unwrap();
+    }
+
+    #[test]
+    fn mock_backend() {
+        let mock = MockVolume::with_devices(&[("usb", "USB Microphone"), ("hdmi", "HDMI Input")]);
+        check_mute(&mock);
+        check_gain(&mock);
+
+        mock.select_device(Some("hdmi")).unwrap();
+        assert_eq!(mock.current_device().unwrap().id, "hdmi");
+        check_mute(&mock);
+        assert!(mock.select_device(Some("missing")).is_err());
     }
 }
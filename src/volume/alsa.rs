@@ -0,0 +1,349 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use alsa::card;
+use alsa::mixer::{MilliBel, Mixer, Selem, SelemChannelId, SelemId};
+use alsa::Round;
+use anyhow::{Context, Result};
+
+use super::{CaptureDevice, MicBackend};
+
+/// 直接操作 ALSA mixer 的 "Capture" 元素, 用于没有声音服务器的系统.
+/// 优先使用 Capture Switch, 没有开关时把 Capture Volume 调到最小来代替静音
+pub struct AlsaVolume {
+    card: RefCell<String>,
+    mixer: RefCell<Mixer>,
+
+    // 音量归零前各声卡各声道的音量, 取消静音时恢复
+    saved_volume: RefCell<BTreeMap<String, Vec<(SelemChannelId, i64)>>>,
+}
+
+impl AlsaVolume {
+    pub fn new() -> Result<Self> {
+        Self::with_card(&default_card())
+    }
+
+    pub fn with_card(card: &str) -> Result<Self> {
+        let volume = AlsaVolume {
+            card: RefCell::new(card.to_string()),
+            mixer: RefCell::new(open_mixer(card)?),
+            saved_volume: RefCell::new(BTreeMap::new()),
+        };
+        volume.with_selem(|_| Ok(()))?;
+
+        Ok(volume)
+    }
+
+    fn with_selem<T>(&self, f: impl FnOnce(&Selem) -> Result<T>) -> Result<T> {
+        let mixer = self.mixer.borrow();
+        // 读取前先处理挂起的事件, 否则拿到的可能是其他程序修改之前的值
+        mixer.handle_events().ok();
+
+        f(&find_selem(&mixer, &self.card.borrow())?)
+    }
+
+    /// 操作指定声卡, 不是当前声卡时临时打开一个 mixer
+    fn with_card_selem<T>(&self, card: &str, f: impl FnOnce(&Selem) -> Result<T>) -> Result<T> {
+        if *self.card.borrow() == card {
+            return self.with_selem(f);
+        }
+
+        let mixer = open_mixer(card)?;
+        let selem = find_selem(&mixer, card)?;
+        f(&selem)
+    }
+
+    fn channels(selem: &Selem) -> Vec<SelemChannelId> {
+        SelemChannelId::all()
+            .iter()
+            .copied()
+            .filter(|&channel| selem.has_capture_channel(channel))
+            .collect()
+    }
+
+    fn set_mute(&self, mute: bool) -> Result<()> {
+        let card = self.card.borrow().clone();
+        self.with_selem(|selem| self.set_selem_mute(&card, selem, mute))
+    }
+
+    fn set_selem_mute(&self, card: &str, selem: &Selem, mute: bool) -> Result<()> {
+        if selem.has_capture_switch() {
+            selem.set_capture_switch_all(!mute as i32)?;
+            return Ok(());
+        }
+
+        let mut saved_volume = self.saved_volume.borrow_mut();
+        if mute {
+            if saved_volume.contains_key(card) {
+                return Ok(());
+            }
+
+            let (min, _) = selem.get_capture_volume_range();
+            let mut saved = Vec::new();
+            for channel in Self::channels(selem) {
+                saved.push((channel, selem.get_capture_volume(channel)?));
+                selem.set_capture_volume(channel, min)?;
+            }
+            saved_volume.insert(card.to_string(), saved);
+        } else if let Some(saved) = saved_volume.remove(card) {
+            for (channel, value) in saved {
+                selem.set_capture_volume(channel, value)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 操作带有 Capture Volume 的元素, 同时传入所有录音声道, 没有音量控制时返回错误
+    fn with_volume_selem<T>(
+        &self,
+        f: impl FnOnce(&Selem, Vec<SelemChannelId>) -> Result<T>,
+    ) -> Result<T> {
+        self.with_selem(|selem| {
+            if !selem.has_capture_volume() {
+                return Err(anyhow::anyhow!(
+                    "No capture volume control found on {}",
+                    self.card.borrow()
+                ));
+            }
+
+            f(selem, Self::channels(selem))
+        })
+    }
+
+    fn get_mute(&self) -> Result<bool> {
+        self.with_selem(Self::selem_mute)
+    }
+
+    fn selem_mute(selem: &Selem) -> Result<bool> {
+        let channels = Self::channels(selem);
+
+        if selem.has_capture_switch() {
+            for channel in channels {
+                if selem.get_capture_switch(channel)? != 0 {
+                    return Ok(false);
+                }
+            }
+
+            return Ok(true);
+        }
+
+        let (min, _) = selem.get_capture_volume_range();
+        for channel in channels {
+            if selem.get_capture_volume(channel)? > min {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+/// 通过 `MUTE_ALSA_CARD` 环境变量选择声卡, 例如 `hw:1`, 默认为 `default`
+fn default_card() -> String {
+    std::env::var("MUTE_ALSA_CARD").unwrap_or_else(|_| "default".to_string())
+}
+
+fn open_mixer(card: &str) -> Result<Mixer> {
+    Mixer::new(card, false).with_context(|| format!("Open ALSA mixer of {} failed", card))
+}
+
+fn find_selem<'a>(mixer: &'a Mixer, card: &str) -> Result<Selem<'a>> {
+    match mixer.find_selem(&SelemId::new("Capture", 0)) {
+        Some(selem) => Ok(selem),
+        None => mixer
+            .iter()
+            .filter_map(Selem::new)
+            .find(|selem| selem.has_capture_switch() || selem.has_capture_volume())
+            .with_context(|| format!("No capture control found on {}", card)),
+    }
+}
+
+impl MicBackend for AlsaVolume {
+    fn mute(&self) -> bool {
+        self.set_mute(true).is_ok()
+    }
+
+    fn unmute(&self) -> bool {
+        self.set_mute(false).is_ok()
+    }
+
+    fn toggle(&self) {
+        if let Ok(mute) = self.get_mute() {
+            self.set_mute(!mute).ok();
+        }
+    }
+
+    fn is_mute(&self) -> bool {
+        self.get_mute().unwrap_or(false)
+    }
+
+    fn devices(&self) -> Result<Vec<CaptureDevice>> {
+        let mut devices = Vec::new();
+
+        for card in card::Iter::new() {
+            let card = card?;
+            let id = format!("hw:{}", card.get_index());
+
+            // 只列出带有录音控制的声卡
+            let has_capture = open_mixer(&id)
+                .map(|mixer| {
+                    mixer
+                        .iter()
+                        .filter_map(Selem::new)
+                        .any(|selem| selem.has_capture_switch() || selem.has_capture_volume())
+                })
+                .unwrap_or(false);
+            if !has_capture {
+                continue;
+            }
+
+            let name = card.get_longname().or_else(|_| card.get_name())?;
+            devices.push(CaptureDevice { id, name });
+        }
+
+        Ok(devices)
+    }
+
+    fn current_device(&self) -> Option<CaptureDevice> {
+        let id = self.card.borrow().clone();
+        let name = id
+            .strip_prefix("hw:")
+            .and_then(|index| index.parse().ok())
+            .and_then(|index| card::Card::new(index).get_longname().ok())
+            .unwrap_or_else(|| id.clone());
+
+        Some(CaptureDevice { id, name })
+    }
+
+    fn select_device(&self, id: Option<&str>) -> Result<()> {
+        let card = id.map_or_else(default_card, |it| it.to_string());
+        let mixer = open_mixer(&card)?;
+
+        // 音量归零只对原来的声卡有效, 换卡前先恢复. 用开关静音的声卡保持原样
+        let old = self.card.borrow().clone();
+        if self.saved_volume.borrow().contains_key(&old) {
+            self.with_selem(|selem| {
+                if selem.has_capture_switch() {
+                    return Ok(());
+                }
+                self.set_selem_mute(&old, selem, false)
+            })
+            .ok();
+        }
+
+        *self.mixer.borrow_mut() = mixer;
+        *self.card.borrow_mut() = card;
+        self.with_selem(|_| Ok(()))
+    }
+
+    fn set_device_mute(&self, id: &str, mute: bool) -> Result<()> {
+        self.with_card_selem(id, |selem| self.set_selem_mute(id, selem, mute))
+    }
+
+    fn device_mute(&self, id: &str) -> Result<bool> {
+        self.with_card_selem(id, Self::selem_mute)
+    }
+
+    fn gain(&self) -> Result<f32> {
+        self.with_volume_selem(|selem, channels| {
+            let (min, max) = selem.get_capture_volume_range();
+            let mut value = min;
+            for channel in channels {
+                value = value.max(selem.get_capture_volume(channel)?);
+            }
+
+            if max <= min {
+                return Ok(1.0);
+            }
+            Ok((value - min) as f32 / (max - min) as f32)
+        })
+    }
+
+    fn set_gain(&self, gain: f32) -> Result<()> {
+        self.with_volume_selem(|selem, _| {
+            let (min, max) = selem.get_capture_volume_range();
+            let gain = gain.clamp(0.0, 1.0);
+            let value = min + ((max - min) as f32 * gain).round() as i64;
+
+            selem.set_capture_volume_all(value)?;
+            Ok(())
+        })
+    }
+
+    fn gain_db(&self) -> Result<f32> {
+        self.with_volume_selem(|selem, channels| {
+            let (MilliBel(min), _) = selem.get_capture_db_range();
+            let mut value = min;
+            for channel in channels {
+                value = value.max(selem.get_capture_vol_db(channel)?.0);
+            }
+
+            Ok(value as f32 / 100.0)
+        })
+    }
+
+    fn set_gain_db(&self, db: f32) -> Result<()> {
+        self.with_volume_selem(|selem, channels| {
+            let value = MilliBel((db * 100.0).round() as i64);
+            for channel in channels {
+                selem.set_capture_db(channel, value, Round::Floor)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn gain_range_db(&self) -> Result<(f32, f32)> {
+        self.with_volume_selem(|selem, _| {
+            let (MilliBel(min), MilliBel(max)) = selem.get_capture_db_range();
+
+            Ok((min as f32 / 100.0, max as f32 / 100.0))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::volume::tests::{check_gain, check_mute};
+
+    /// 需要先加载 snd-dummy, 见 README
+    fn dummy_card() -> String {
+        let index = card::Iter::new()
+            .filter_map(|card| card.ok())
+            .find(|card| card.get_name().map_or(false, |name| name == "Dummy"))
+            .expect("snd-dummy not loaded")
+            .get_index();
+
+        format!("hw:{}", index)
+    }
+
+    #[test]
+    #[ignore]
+    fn dummy_card_mute() {
+        let volume = AlsaVolume::with_card(&dummy_card()).unwrap();
+
+        check_mute(&volume);
+        // snd-dummy 的录音控制只有开关, 没有音量
+        if volume.gain().is_ok() {
+            check_gain(&volume);
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn select_device_keeps_mute() {
+        let card = dummy_card();
+        let volume = AlsaVolume::with_card(&card).unwrap();
+        let muted = volume.is_mute();
+
+        assert!(volume.mute());
+        volume.select_device(Some(&card)).unwrap();
+        assert!(volume.device_mute(&card).unwrap());
+        volume.select_device(None).ok();
+        assert!(volume.device_mute(&card).unwrap());
+
+        volume.set_device_mute(&card, muted).unwrap();
+    }
+}
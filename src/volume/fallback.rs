@@ -0,0 +1,371 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use anyhow::{Context, Result};
+
+use super::{CaptureDevice, CaptureStream, MicBackend, MuteState, VolumeEvent};
+use crate::config::{Config, DeviceConfig, MuteStrategy};
+
+/// 部分 USB 和虚拟设备会忽略静音开关, 这时改为把音量调到 0 来静音.
+/// 归零前的音量保存在配置文件里, 重启后取消静音也能恢复到原来的音量
+pub struct GainFallback {
+    inner: Rc<dyn MicBackend>,
+    config: Rc<RefCell<Config>>,
+}
+
+impl GainFallback {
+    pub fn new(inner: Rc<dyn MicBackend>, config: Rc<RefCell<Config>>) -> Self {
+        GainFallback { inner, config }
+    }
+
+    fn current_id(&self) -> Option<String> {
+        self.inner.current_device().map(|it| it.id)
+    }
+
+    fn strategy(&self, id: &str) -> MuteStrategy {
+        self.config.borrow().device(id).strategy
+    }
+
+    fn saved_gain(&self, id: &str) -> Option<f32> {
+        self.config.borrow().device(id).saved_gain
+    }
+
+    fn store_saved_gain(&self, id: &str, gain: Option<f32>) {
+        self.update_device(id, |device| device.saved_gain = gain);
+    }
+
+    /// 修改后立即写入配置文件
+    fn update_device(&self, id: &str, f: impl FnOnce(&mut DeviceConfig)) {
+        let mut config = self.config.borrow_mut();
+        f(config.device_mut(id));
+
+        if let Err(e) = config.save() {
+            eprintln!("Save config failed: {:#}", e);
+        }
+    }
+
+    fn gain_mute(&self, id: &str) -> Result<()> {
+        // 已经归零时不能再保存一次, 否则保存的就是 0
+        if !self.gain_muted(id) {
+            let gain = self.inner.gain().context("Capture gain unavailable")?;
+            self.store_saved_gain(id, Some(gain));
+        }
+
+        self.inner.set_gain(0.0)
+    }
+
+    fn gain_unmute(&self, id: &str) -> Result<()> {
+        if let Some(gain) = self.saved_gain(id) {
+            self.inner.set_gain(gain)?;
+            self.store_saved_gain(id, None);
+        }
+
+        Ok(())
+    }
+
+    fn set_mute(&self, mute: bool) -> bool {
+        let id = match self.current_id() {
+            Some(id) => id,
+            None => return if mute { self.inner.mute() } else { self.inner.unmute() },
+        };
+
+        let result = if mute {
+            match self.strategy(&id) {
+                MuteStrategy::Native => return self.inner.mute(),
+                MuteStrategy::Gain => self.gain_mute(&id),
+                MuteStrategy::Auto => {
+                    if self.inner.mute() && self.inner.is_mute() {
+                        return true;
+                    }
+
+                    // 静音开关没有生效, 以后这个设备直接使用音量归零
+                    eprintln!("Mute switch of {} ignored, falling back to gain", id);
+                    self.update_device(&id, |device| device.strategy = MuteStrategy::Gain);
+                    self.gain_mute(&id)
+                }
+            }
+        } else {
+            // 保存过音量就恢复, 策略可能在静音期间被修改过
+            self.gain_unmute(&id).and_then(|_| {
+                if self.strategy(&id) != MuteStrategy::Gain && !self.inner.unmute() {
+                    return Err(anyhow::anyhow!("Unmute failed"));
+                }
+
+                Ok(())
+            })
+        };
+
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                eprintln!("Gain fallback failed: {:#}", e);
+                false
+            }
+        }
+    }
+
+    /// 处于归零静音状态: 保存过音量并且当前音量仍然是 0
+    fn gain_muted(&self, id: &str) -> bool {
+        self.saved_gain(id).is_some() && self.inner.gain().map_or(false, |gain| gain <= 0.0)
+    }
+
+    /// 处于归零静音状态的当前设备
+    fn gain_muted_id(&self) -> Option<String> {
+        self.current_id().filter(|id| self.gain_muted(id))
+    }
+}
+
+impl MicBackend for GainFallback {
+    fn mute(&self) -> bool {
+        self.set_mute(true)
+    }
+
+    fn unmute(&self) -> bool {
+        self.set_mute(false)
+    }
+
+    fn toggle(&self) {
+        self.set_mute(!self.is_mute());
+    }
+
+    fn is_mute(&self) -> bool {
+        let gain_muted = self.current_id().map_or(false, |id| self.gain_muted(&id));
+
+        gain_muted || self.inner.is_mute()
+    }
+
+    fn mute_state(&self) -> MuteState {
+        if self.is_mute() {
+            MuteState::Muted
+        } else {
+            MuteState::Live
+        }
+    }
+
+    fn devices(&self) -> Result<Vec<CaptureDevice>> {
+        self.inner.devices()
+    }
+
+    fn current_device(&self) -> Option<CaptureDevice> {
+        self.inner.current_device()
+    }
+
+    fn select_device(&self, id: Option<&str>) -> Result<()> {
+        self.inner.select_device(id)
+    }
+
+    /// 音量只能操作当前设备, 其他设备只使用静音开关
+    fn set_device_mute(&self, id: &str, mute: bool) -> Result<()> {
+        if self.current_id().as_deref() == Some(id) {
+            if self.set_mute(mute) {
+                return Ok(());
+            }
+
+            return Err(anyhow::anyhow!("Set mute of {} failed", id));
+        }
+
+        self.inner.set_device_mute(id, mute)
+    }
+
+    fn device_mute(&self, id: &str) -> Result<bool> {
+        if self.current_id().as_deref() == Some(id) {
+            return Ok(self.is_mute());
+        }
+
+        self.inner.device_mute(id)
+    }
+
+    /// 归零静音时返回取消静音后会恢复的音量
+    fn gain(&self) -> Result<f32> {
+        match self.gain_muted_id().and_then(|id| self.saved_gain(&id)) {
+            Some(gain) => Ok(gain),
+            None => self.inner.gain(),
+        }
+    }
+
+    /// 归零静音时只修改保存的音量, 保持静音, 取消静音时恢复到新的音量
+    fn set_gain(&self, gain: f32) -> Result<()> {
+        match self.gain_muted_id() {
+            Some(id) => {
+                self.store_saved_gain(&id, Some(gain.clamp(0.0, 1.0)));
+                Ok(())
+            }
+            None => self.inner.set_gain(gain),
+        }
+    }
+
+    fn gain_db(&self) -> Result<f32> {
+        self.inner.gain_db()
+    }
+
+    /// 分贝和音量的换算由设备决定, 先设置到设备上读出音量再归零
+    fn set_gain_db(&self, db: f32) -> Result<()> {
+        let id = match self.gain_muted_id() {
+            Some(id) => id,
+            None => return self.inner.set_gain_db(db),
+        };
+
+        self.inner.set_gain_db(db)?;
+        let gain = self.inner.gain()?;
+        self.store_saved_gain(&id, Some(gain));
+        self.inner.set_gain(0.0)
+    }
+
+    fn gain_range_db(&self) -> Result<(f32, f32)> {
+        self.inner.gain_range_db()
+    }
+
+    fn streams(&self) -> Result<Vec<CaptureStream>> {
+        self.inner.streams()
+    }
+
+    fn set_stream_mute(&self, id: u32, mute: bool) -> Result<()> {
+        self.inner.set_stream_mute(id, mute)
+    }
+
+    fn subscribe(&self, sender: glib::Sender<VolumeEvent>) -> Result<()> {
+        self.inner.subscribe(sender)
+    }
+
+    fn rebind(&self) -> Result<()> {
+        self.inner.rebind()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::volume::{MockVolume, MuteScope};
+
+    fn new_fallback(strategy: MuteStrategy) -> (Rc<MockVolume>, GainFallback) {
+        let mut config = Config::default();
+        config.device_mut("mock").strategy = strategy;
+
+        let mock = Rc::new(MockVolume::new());
+        mock.set_gain(0.6).unwrap();
+        let fallback = GainFallback::new(mock.clone(), Rc::new(RefCell::new(config)));
+        (mock, fallback)
+    }
+
+    #[test]
+    fn gain_strategy_restores_gain() {
+        let (mock, fallback) = new_fallback(MuteStrategy::Gain);
+
+        assert!(fallback.mute());
+        assert_eq!(mock.gain().unwrap(), 0.0);
+        assert!(!mock.is_mute());
+        assert!(fallback.is_mute());
+        assert_eq!(fallback.gain().unwrap(), 0.6);
+
+        // 再静音一次不能把 0 保存下来
+        assert!(fallback.mute());
+        assert!(fallback.unmute());
+        assert_eq!(mock.gain().unwrap(), 0.6);
+        assert!(!fallback.is_mute());
+        assert_eq!(fallback.config.borrow().device("mock").saved_gain, None);
+    }
+
+    #[test]
+    fn auto_strategy_uses_mute_switch() {
+        let (mock, fallback) = new_fallback(MuteStrategy::Auto);
+
+        assert!(fallback.mute());
+        assert!(mock.is_mute());
+        assert_eq!(mock.gain().unwrap(), 0.6);
+        assert_eq!(fallback.strategy("mock"), MuteStrategy::Auto);
+    }
+
+    #[test]
+    fn auto_strategy_detects_ignored_mute() {
+        let (mock, fallback) = new_fallback(MuteStrategy::Auto);
+        mock.ignore_mute("mock");
+
+        assert!(fallback.mute());
+        assert!(!mock.is_mute());
+        assert_eq!(mock.gain().unwrap(), 0.0);
+        assert!(fallback.is_mute());
+        assert_eq!(fallback.strategy("mock"), MuteStrategy::Gain);
+
+        assert!(fallback.unmute());
+        assert_eq!(mock.gain().unwrap(), 0.6);
+
+        // 检测结果保存在配置里, 重启后直接使用音量归零
+        let config = Rc::new(RefCell::new(Config::load().unwrap()));
+        let fallback = GainFallback::new(mock.clone(), config);
+        assert!(fallback.mute());
+        assert_eq!(mock.gain().unwrap(), 0.0);
+    }
+
+    #[test]
+    fn native_strategy_uses_mute_switch() {
+        let (mock, fallback) = new_fallback(MuteStrategy::Native);
+
+        assert!(fallback.mute());
+        assert!(mock.is_mute());
+        assert_eq!(mock.gain().unwrap(), 0.6);
+        assert!(fallback.unmute());
+        assert!(!mock.is_mute());
+    }
+
+    #[test]
+    fn set_gain_while_muted() {
+        let (mock, fallback) = new_fallback(MuteStrategy::Gain);
+
+        fallback.mute();
+        fallback.set_gain(0.3).unwrap();
+        assert_eq!(mock.gain().unwrap(), 0.0);
+        assert_eq!(fallback.gain().unwrap(), 0.3);
+
+        fallback.unmute();
+        assert_eq!(mock.gain().unwrap(), 0.3);
+    }
+
+    #[test]
+    fn external_gain_change_is_saved_again() {
+        let (mock, fallback) = new_fallback(MuteStrategy::Gain);
+
+        fallback.mute();
+        // 其他程序在静音期间调高了音量, 视为已经取消静音
+        mock.set_gain(0.8).unwrap();
+        assert!(!fallback.is_mute());
+
+        fallback.mute();
+        fallback.unmute();
+        assert_eq!(mock.gain().unwrap(), 0.8);
+    }
+
+    #[test]
+    fn saved_gain_survives_restart() {
+        let (mock, fallback) = new_fallback(MuteStrategy::Gain);
+        fallback.mute();
+
+        let config = Rc::new(RefCell::new(Config::load().unwrap()));
+        let fallback = GainFallback::new(mock.clone(), config);
+        assert!(fallback.is_mute());
+        fallback.unmute();
+        assert_eq!(mock.gain().unwrap(), 0.6);
+    }
+
+    #[test]
+    fn push_restores_gain_mute() {
+        let (mock, fallback) = new_fallback(MuteStrategy::Gain);
+        let config = fallback.config.clone();
+        let scope = MuteScope::new(Rc::new(fallback), config);
+
+        // 按住说话: 按下时取消静音, 松开时恢复按下前的状态
+        scope.mute();
+        let snapshot = scope.snapshot();
+        scope.unmute();
+        assert_eq!(mock.gain().unwrap(), 0.6);
+        scope.restore(&snapshot);
+        assert_eq!(mock.gain().unwrap(), 0.0);
+
+        // 按住静音
+        scope.unmute();
+        let snapshot = scope.snapshot();
+        scope.mute();
+        scope.restore(&snapshot);
+        assert_eq!(mock.gain().unwrap(), 0.6);
+        assert!(!scope.is_mute());
+    }
+}
@@ -0,0 +1,355 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use jack::{
+    AsyncClient, AudioIn, AudioOut, Client, ClientOptions, Control, Port, PortFlags,
+    ProcessHandler, ProcessScope,
+};
+
+use super::{amplitude_to_db, db_to_amplitude, CaptureDevice, MicBackend, SOFT_GAIN_RANGE_DB};
+
+const CLIENT_NAME: &str = "mute_assistant";
+
+/// 在 process 回调里把输入端口复制到输出端口, 静音时输出静默
+struct Gate {
+    inputs: Vec<Port<AudioIn>>,
+    outputs: Vec<Port<AudioOut>>,
+
+    // 每个 input 单独的静音开关
+    muted: Arc<Vec<AtomicBool>>,
+    // f32 的位表示, 所有 input 共用的增益
+    gain: Arc<AtomicU32>,
+}
+
+impl ProcessHandler for Gate {
+    fn process(&mut self, _client: &Client, ps: &ProcessScope) -> Control {
+        let gain = f32::from_bits(self.gain.load(Ordering::Relaxed));
+        let ports = self.inputs.iter().zip(self.outputs.iter_mut());
+
+        for ((input, output), muted) in ports.zip(self.muted.iter()) {
+            let output = output.as_mut_slice(ps);
+            if muted.load(Ordering::Relaxed) {
+                output.iter_mut().for_each(|sample| *sample = 0.0);
+            } else if gain == 1.0 {
+                output.copy_from_slice(input.as_slice(ps));
+            } else {
+                for (sample, &value) in output.iter_mut().zip(input.as_slice(ps)) {
+                    *sample = value * gain;
+                }
+            }
+        }
+
+        Control::Continue
+    }
+}
+
+/// JACK 下没有设备级别的静音, 这里注册一个 client 作为门限:
+/// 选中的采集端口接到 `mute_assistant:in_N`, 其他程序改为从 `mute_assistant:capture_N` 录音
+pub struct JackVolume {
+    client: AsyncClient<(), Gate>,
+
+    // 启动时选择的采集端口, 与 inputs 一一对应
+    default_sources: Vec<String>,
+    // 用户固定的采集端口, 会同时接到所有 inputs 上
+    pinned: RefCell<Option<String>>,
+    inputs: Vec<String>,
+
+    muted: Arc<Vec<AtomicBool>>,
+    gain: Arc<AtomicU32>,
+}
+
+impl JackVolume {
+    /// 通过 `MUTE_JACK_PORTS` 环境变量指定采集端口, 逗号分隔, 默认为所有物理采集端口
+    pub fn new() -> Result<Self> {
+        let (client, _status) = Client::new(CLIENT_NAME, ClientOptions::NO_START_SERVER)
+            .context("Connect to JACK server failed")?;
+
+        let sources = match std::env::var("MUTE_JACK_PORTS") {
+            Ok(ports) => ports
+                .split(',')
+                .map(|it| it.trim().to_string())
+                .filter(|it| !it.is_empty())
+                .collect(),
+            Err(_) => capture_ports(&client),
+        };
+        if sources.is_empty() {
+            return Err(anyhow::anyhow!("No JACK capture port found"));
+        }
+
+        let mut inputs = Vec::new();
+        let mut outputs = Vec::new();
+        let mut input_names = Vec::new();
+        for i in 1..=sources.len() {
+            let input = client
+                .register_port(&format!("in_{}", i), AudioIn::default())
+                .context("Register JACK input port failed")?;
+            input_names.push(input.name().context("Get JACK port name failed")?);
+            inputs.push(input);
+
+            outputs.push(
+                client
+                    .register_port(&format!("capture_{}", i), AudioOut::default())
+                    .context("Register JACK output port failed")?,
+            );
+        }
+
+        let muted: Arc<Vec<_>> =
+            Arc::new(sources.iter().map(|_| AtomicBool::new(false)).collect());
+        let gain = Arc::new(AtomicU32::new(1f32.to_bits()));
+        let gate = Gate {
+            inputs,
+            outputs,
+            muted: muted.clone(),
+            gain: gain.clone(),
+        };
+        let client = client
+            .activate_async((), gate)
+            .context("Activate JACK client failed")?;
+
+        let volume = JackVolume {
+            client,
+            default_sources: sources,
+            pinned: RefCell::new(None),
+            inputs: input_names,
+            muted,
+            gain,
+        };
+        volume.connect(&volume.routes(None))?;
+
+        Ok(volume)
+    }
+
+    fn routes(&self, pinned: Option<&str>) -> Vec<(String, String)> {
+        match pinned {
+            Some(port) => self
+                .inputs
+                .iter()
+                .map(|input| (port.to_string(), input.clone()))
+                .collect(),
+            None => self
+                .default_sources
+                .iter()
+                .cloned()
+                .zip(self.inputs.iter().cloned())
+                .collect(),
+        }
+    }
+
+    /// 连接到某个采集端口的 input 序号
+    fn inputs_of(&self, port: &str) -> Vec<usize> {
+        let routes = self.routes(self.pinned.borrow().as_deref());
+
+        routed_inputs(&routes, &self.inputs, port)
+    }
+
+    fn set_all(&self, mute: bool) {
+        for muted in self.muted.iter() {
+            muted.store(mute, Ordering::Relaxed);
+        }
+    }
+
+    fn connect(&self, routes: &[(String, String)]) -> Result<()> {
+        for (source, input) in routes {
+            self.client
+                .as_client()
+                .connect_ports_by_name(source, input)
+                .with_context(|| format!("Connect {} to {} failed", source, input))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// 没有经过 mute_assistant 的端口返回空列表
+fn routed_inputs(routes: &[(String, String)], inputs: &[String], port: &str) -> Vec<usize> {
+    routes
+        .iter()
+        .filter(|(source, _)| source == port)
+        .filter_map(|(_, input)| inputs.iter().position(|it| it == input))
+        .collect()
+}
+
+fn capture_ports(client: &Client) -> Vec<String> {
+    client.ports(
+        None,
+        Some("audio"),
+        PortFlags::IS_OUTPUT | PortFlags::IS_PHYSICAL,
+    )
+}
+
+impl MicBackend for JackVolume {
+    fn mute(&self) -> bool {
+        self.set_all(true);
+        true
+    }
+
+    fn unmute(&self) -> bool {
+        self.set_all(false);
+        true
+    }
+
+    fn toggle(&self) {
+        self.set_all(!self.is_mute());
+    }
+
+    fn is_mute(&self) -> bool {
+        self.muted.iter().all(|it| it.load(Ordering::Relaxed))
+    }
+
+    fn devices(&self) -> Result<Vec<CaptureDevice>> {
+        Ok(capture_ports(self.client.as_client())
+            .into_iter()
+            .map(|port| CaptureDevice {
+                id: port.clone(),
+                name: port,
+            })
+            .collect())
+    }
+
+    fn current_device(&self) -> Option<CaptureDevice> {
+        let pinned = self.pinned.borrow();
+        let port = pinned.as_ref().or_else(|| self.default_sources.first())?;
+
+        Some(CaptureDevice {
+            id: port.clone(),
+            name: port.clone(),
+        })
+    }
+
+    fn select_device(&self, id: Option<&str>) -> Result<()> {
+        if let Some(id) = id {
+            if self.client.as_client().port_by_name(id).is_none() {
+                return Err(anyhow::anyhow!("Capture port not found: {}", id));
+            }
+        }
+
+        let old = self.routes(self.pinned.borrow().as_deref());
+        for (source, input) in &old {
+            self.client
+                .as_client()
+                .disconnect_ports_by_name(source, input)
+                .ok();
+        }
+
+        self.connect(&self.routes(id))?;
+        *self.pinned.borrow_mut() = id.map(|it| it.to_string());
+
+        Ok(())
+    }
+
+    /// 没有经过 mute_assistant 的端口无法静音, 直接跳过
+    fn set_device_mute(&self, id: &str, mute: bool) -> Result<()> {
+        for index in self.inputs_of(id) {
+            self.muted[index].store(mute, Ordering::Relaxed);
+        }
+
+        Ok(())
+    }
+
+    /// 没有经过 mute_assistant 的端口读不到状态, 全部输入模式下不参与统计
+    fn device_mute(&self, id: &str) -> Result<bool> {
+        let indexes = self.inputs_of(id);
+        if indexes.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Capture port not routed through {}: {}",
+                CLIENT_NAME,
+                id
+            ));
+        }
+
+        Ok(indexes
+            .into_iter()
+            .all(|index| self.muted[index].load(Ordering::Relaxed)))
+    }
+
+    /// JACK 没有硬件音量, 增益在 process 回调里用软件实现
+    fn gain(&self) -> Result<f32> {
+        Ok(f32::from_bits(self.gain.load(Ordering::Relaxed)))
+    }
+
+    fn set_gain(&self, gain: f32) -> Result<()> {
+        let gain = gain.clamp(0.0, 1.0);
+        self.gain.store(gain.to_bits(), Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    fn gain_db(&self) -> Result<f32> {
+        self.gain().map(amplitude_to_db)
+    }
+
+    fn set_gain_db(&self, db: f32) -> Result<()> {
+        self.set_gain(db_to_amplitude(db))
+    }
+
+    fn gain_range_db(&self) -> Result<(f32, f32)> {
+        Ok(SOFT_GAIN_RANGE_DB)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::volume::tests::{check_gain, check_mute};
+    use crate::volume::{MuteScope, MuteState, ScopeMode};
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|it| it.to_string()).collect()
+    }
+
+    #[test]
+    fn unrouted_port_has_no_inputs() {
+        let inputs = names(&["mute_assistant:in_1", "mute_assistant:in_2"]);
+        // 固定了 capture_1, capture_2 没有接到任何 input
+        let routes: Vec<(String, String)> = inputs
+            .iter()
+            .map(|input| ("system:capture_1".to_string(), input.clone()))
+            .collect();
+
+        assert_eq!(routed_inputs(&routes, &inputs, "system:capture_1"), vec![0, 1]);
+        assert!(routed_inputs(&routes, &inputs, "system:capture_2").is_empty());
+    }
+
+    fn connected(volume: &JackVolume, source: &str, input: &str) -> bool {
+        let client = volume.client.as_client();
+        let port = client.port_by_name(input).unwrap();
+
+        port.is_connected_to(source).unwrap()
+    }
+
+    /// 需要先启动使用 dummy 驱动的 jackd, 见 README
+    #[test]
+    #[ignore]
+    fn dummy_driver() {
+        let volume = Rc::new(JackVolume::new().unwrap());
+        let sources = volume.default_sources.clone();
+        assert!(connected(&volume, &sources[0], &volume.inputs[0]));
+
+        // 固定采集端口后所有 input 都接到这个端口上
+        let pinned = sources.last().unwrap();
+        volume.select_device(Some(pinned)).unwrap();
+        for input in &volume.inputs {
+            assert!(connected(&volume, pinned, input));
+        }
+
+        check_mute(&*volume);
+        check_gain(&*volume);
+
+        // 其他采集端口没有接到 input, 全部输入模式下跳过它们
+        let mut config = Config::default();
+        config.current_mut().scope = ScopeMode::AllInputs;
+        let scope = MuteScope::new(volume.clone(), Rc::new(RefCell::new(config)));
+        assert!(scope.mute());
+        assert_eq!(scope.mute_state(), MuteState::Muted);
+        assert!(scope.unmute());
+        assert_eq!(scope.mute_state(), MuteState::Live);
+
+        volume.select_device(None).unwrap();
+        assert!(connected(&volume, &sources[0], &volume.inputs[0]));
+    }
+}
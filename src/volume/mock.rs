@@ -0,0 +1,193 @@
+use std::cell::{Cell, RefCell};
+
+use anyhow::Result;
+
+use super::{
+    amplitude_to_db, db_to_amplitude, CaptureDevice, CaptureStream, MicBackend, SOFT_GAIN_RANGE_DB,
+};
+
+struct MockDevice {
+    device: CaptureDevice,
+    muted: Cell<bool>,
+    gain: Cell<f32>,
+    // 模拟忽略静音开关的设备, 设置静音成功但没有效果
+    ignores_mute: Cell<bool>,
+}
+
+impl MockDevice {
+    fn set_muted(&self, mute: bool) {
+        if !self.ignores_mute.get() {
+            self.muted.set(mute);
+        }
+    }
+}
+
+/// 只在内存中记录静音状态, 不接触任何音频设备
+pub struct MockVolume {
+    devices: Vec<MockDevice>,
+
+    // 第一个设备视为系统默认设备
+    pinned: RefCell<Option<usize>>,
+
+    streams: RefCell<Vec<CaptureStream>>,
+}
+
+impl MockVolume {
+    pub fn new() -> Self {
+        Self::with_devices(&[("mock", "Mock Microphone")])
+    }
+
+    pub fn with_devices(devices: &[(&str, &str)]) -> Self {
+        let devices = devices
+            .iter()
+            .map(|&(id, name)| MockDevice {
+                device: CaptureDevice {
+                    id: id.to_string(),
+                    name: name.to_string(),
+                },
+                muted: Cell::new(false),
+                gain: Cell::new(1.0),
+                ignores_mute: Cell::new(false),
+            })
+            .collect();
+
+        MockVolume {
+            devices,
+            pinned: RefCell::new(None),
+            streams: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// 模拟一个程序开始录音
+    #[cfg(test)]
+    pub fn add_stream(&self, id: u32, app: &str) {
+        self.streams.borrow_mut().push(CaptureStream {
+            id,
+            app: app.to_string(),
+            name: format!("{} capture", app),
+            muted: false,
+        });
+    }
+
+    #[cfg(test)]
+    pub fn ignore_mute(&self, id: &str) {
+        self.find(id).unwrap().ignores_mute.set(true);
+    }
+
+    fn current(&self) -> Option<&MockDevice> {
+        self.devices.get(self.pinned.borrow().unwrap_or(0))
+    }
+
+    fn find(&self, id: &str) -> Result<&MockDevice> {
+        self.devices
+            .iter()
+            .find(|it| it.device.id == id)
+            .ok_or_else(|| anyhow::anyhow!("Capture device not found: {}", id))
+    }
+
+    fn set_mute(&self, mute: bool) -> bool {
+        match self.current() {
+            Some(device) => {
+                device.set_muted(mute);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Default for MockVolume {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MicBackend for MockVolume {
+    fn mute(&self) -> bool {
+        self.set_mute(true)
+    }
+
+    fn unmute(&self) -> bool {
+        self.set_mute(false)
+    }
+
+    fn toggle(&self) {
+        self.set_mute(!self.is_mute());
+    }
+
+    fn is_mute(&self) -> bool {
+        self.current().map_or(false, |it| it.muted.get())
+    }
+
+    fn devices(&self) -> Result<Vec<CaptureDevice>> {
+        Ok(self.devices.iter().map(|it| it.device.clone()).collect())
+    }
+
+    fn current_device(&self) -> Option<CaptureDevice> {
+        self.current().map(|it| it.device.clone())
+    }
+
+    fn select_device(&self, id: Option<&str>) -> Result<()> {
+        let index = match id {
+            Some(id) => Some(
+                self.devices
+                    .iter()
+                    .position(|it| it.device.id == id)
+                    .ok_or_else(|| anyhow::anyhow!("Capture device not found: {}", id))?,
+            ),
+            None => None,
+        };
+        *self.pinned.borrow_mut() = index;
+
+        Ok(())
+    }
+
+    fn set_device_mute(&self, id: &str, mute: bool) -> Result<()> {
+        self.find(id)?.set_muted(mute);
+
+        Ok(())
+    }
+
+    fn device_mute(&self, id: &str) -> Result<bool> {
+        Ok(self.find(id)?.muted.get())
+    }
+
+    fn gain(&self) -> Result<f32> {
+        self.current()
+            .map(|it| it.gain.get())
+            .ok_or_else(|| anyhow::anyhow!("No capture device"))
+    }
+
+    fn set_gain(&self, gain: f32) -> Result<()> {
+        self.current()
+            .map(|it| it.gain.set(gain.clamp(0.0, 1.0)))
+            .ok_or_else(|| anyhow::anyhow!("No capture device"))
+    }
+
+    fn gain_db(&self) -> Result<f32> {
+        self.gain().map(amplitude_to_db)
+    }
+
+    fn set_gain_db(&self, db: f32) -> Result<()> {
+        self.set_gain(db_to_amplitude(db))
+    }
+
+    fn gain_range_db(&self) -> Result<(f32, f32)> {
+        Ok(SOFT_GAIN_RANGE_DB)
+    }
+
+    fn streams(&self) -> Result<Vec<CaptureStream>> {
+        Ok(self.streams.borrow().clone())
+    }
+
+    fn set_stream_mute(&self, id: u32, mute: bool) -> Result<()> {
+        let mut streams = self.streams.borrow_mut();
+        let stream = streams
+            .iter_mut()
+            .find(|it| it.id == id)
+            .ok_or_else(|| anyhow::anyhow!("Capture stream not found: {}", id))?;
+        stream.muted = mute;
+
+        Ok(())
+    }
+}
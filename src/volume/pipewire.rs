@@ -0,0 +1,742 @@
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::os::unix::io::AsRawFd;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context as _, Result};
+use pw::context::Context;
+use pw::core::{Core, PW_ID_CORE};
+use pw::device::{Device, DeviceListener};
+use pw::main_loop::MainLoop;
+use pw::metadata::{Metadata, MetadataListener};
+use pw::node::{Node, NodeListener};
+use pw::properties::Properties;
+use pw::registry::{GlobalObject, Listener, Registry};
+use pw::spa::param::ParamType;
+use pw::spa::pod::deserialize::PodDeserializer;
+use pw::spa::pod::serialize::PodSerializer;
+use pw::spa::pod::{Object, Pod, Property, Value, ValueArray};
+use pw::spa::sys::{
+    SPA_PARAM_ROUTE_device, SPA_PARAM_ROUTE_index, SPA_PARAM_ROUTE_props, SPA_PARAM_ROUTE_save,
+    SPA_PROP_channelVolumes, SPA_PROP_mute,
+};
+use pw::spa::utils::SpaTypes;
+use pw::types::ObjectType;
+
+use super::{
+    amplitude_to_db, db_to_amplitude, CaptureDevice, CaptureStream, MicBackend, VolumeEvent,
+    SOFT_GAIN_RANGE_DB,
+};
+
+const SYNC_TIMEOUT: Duration = Duration::from_secs(2);
+
+const SOURCE_CLASS: &str = "Audio/Source";
+// 声卡, 声卡上的输入节点需要通过它的 Route 设置静音和音量
+const DEVICE_CLASS: &str = "Audio/Device";
+// 程序的录音流
+const STREAM_CLASS: &str = "Stream/Input/Audio";
+
+/// 输入设备或者录音流
+struct AudioNode {
+    name: String,
+    description: String,
+    app: String,
+    mute: Rc<Cell<Option<bool>>>,
+    // 各声道的线性音量
+    volumes: Rc<RefCell<Option<Vec<f32>>>>,
+    // 所属声卡的 id 和 card.profile.device, 虚拟节点和录音流没有
+    route: Rc<Cell<Option<(u32, i32)>>>,
+
+    proxy: Node,
+    _listener: NodeListener,
+}
+
+/// 声卡, 只记录当前启用的 Route
+struct AudioDevice {
+    // card.profile.device 到 Route index
+    routes: Rc<RefCell<BTreeMap<i32, i32>>>,
+
+    proxy: Device,
+    _listener: DeviceListener,
+}
+
+#[derive(Default)]
+struct State {
+    // registry 回调里不能直接 bind, 先记下来等 sync 结束后再处理
+    pending: Vec<GlobalObject<Properties>>,
+
+    nodes: BTreeMap<u32, AudioNode>,
+    streams: BTreeMap<u32, AudioNode>,
+    devices: BTreeMap<u32, AudioDevice>,
+    metadata: Option<(Metadata, MetadataListener)>,
+    default_source: Rc<RefCell<Option<String>>>,
+
+    // 用户固定的 node.name, 为 None 时使用默认 source
+    pinned: Option<String>,
+
+    sender: Rc<RefCell<Option<glib::Sender<VolumeEvent>>>>,
+}
+
+impl State {
+    fn send(sender: &RefCell<Option<glib::Sender<VolumeEvent>>>, event: VolumeEvent) {
+        if let Some(sender) = sender.borrow().as_ref() {
+            sender.send(event).ok();
+        }
+    }
+
+    fn node(&self, name: &str) -> Result<&AudioNode> {
+        self.nodes
+            .values()
+            .find(|node| node.name == name)
+            .with_context(|| format!("Audio source node not found: {}", name))
+    }
+
+    /// 和 pipewire-pulse 一样, 声卡上的节点通过 Route 设置, 会话管理器会记住设置.
+    /// 直接改节点的 Props 会在切换 profile 或者端口后丢失
+    fn set_props(&self, node: &AudioNode, property: Property) -> Result<()> {
+        let route = node.route.get().and_then(|(device, card_device)| {
+            let device = self.devices.get(&device)?;
+            let index = *device.routes.borrow().get(&card_device)?;
+            Some((device, index, card_device))
+        });
+
+        let (device, index, card_device) = match route {
+            Some(it) => it,
+            None => return set_node_props(node, property),
+        };
+
+        let route = Value::Object(Object {
+            type_: SpaTypes::ObjectParamRoute.as_raw(),
+            id: ParamType::Route.as_raw(),
+            properties: vec![
+                Property::new(SPA_PARAM_ROUTE_index, Value::Int(index)),
+                Property::new(SPA_PARAM_ROUTE_device, Value::Int(card_device)),
+                Property::new(
+                    SPA_PARAM_ROUTE_props,
+                    Value::Object(Object {
+                        type_: SpaTypes::ObjectParamProps.as_raw(),
+                        id: ParamType::Route.as_raw(),
+                        properties: vec![property],
+                    }),
+                ),
+                Property::new(SPA_PARAM_ROUTE_save, Value::Bool(true)),
+            ],
+        });
+        let bytes = serialize(&route)?;
+        let pod = Pod::from_bytes(&bytes).context("Invalid Route pod")?;
+
+        device.proxy.set_param(ParamType::Route, 0, pod);
+
+        Ok(())
+    }
+}
+
+impl AudioNode {
+    fn capture_device(&self) -> CaptureDevice {
+        CaptureDevice {
+            id: self.name.clone(),
+            name: self.description.clone(),
+        }
+    }
+
+    fn capture_stream(&self, id: u32) -> CaptureStream {
+        CaptureStream {
+            id,
+            app: self.app.clone(),
+            name: self.description.clone(),
+            muted: self.mute.get().unwrap_or(false),
+        }
+    }
+}
+
+fn media_class(global: &GlobalObject<Properties>) -> Option<&str> {
+    global.props.as_ref().and_then(|props| props.get("media.class"))
+}
+
+/// 直接通过 PipeWire 协议控制默认输入节点的静音, 声卡上的节点通过声卡的 Route 设置.
+/// 默认节点从 `default` metadata 的 `default.audio.source` 读取
+pub struct PipeWireVolume {
+    // 字段按声明顺序 drop, proxy 需要先于 core 和 mainloop 释放
+    state: Rc<RefCell<State>>,
+
+    _registry_listener: Listener,
+    registry: Registry,
+    core: Core,
+    _context: Context,
+    mainloop: MainLoop,
+}
+
+impl PipeWireVolume {
+    pub fn new() -> Result<Self> {
+        pw::init();
+
+        let mainloop = MainLoop::new(None).context("Create PipeWire mainloop failed")?;
+        let context = Context::new(&mainloop).context("Create PipeWire context failed")?;
+        let core = context
+            .connect(None)
+            .context("Connect to PipeWire daemon failed")?;
+        let registry = core.get_registry().context("Get PipeWire registry failed")?;
+
+        let state = Rc::new(RefCell::new(State::default()));
+
+        let s = state.clone();
+        let registry_listener = registry
+            .add_listener_local()
+            .global(move |global| {
+                let interesting = match global.type_ {
+                    ObjectType::Node => global
+                        .props
+                        .and_then(|props| props.get("media.class"))
+                        .map_or(false, |class| class == SOURCE_CLASS || class == STREAM_CLASS),
+                    ObjectType::Device => global
+                        .props
+                        .and_then(|props| props.get("media.class"))
+                        .map_or(false, |class| class == DEVICE_CLASS),
+                    ObjectType::Metadata => global
+                        .props
+                        .and_then(|props| props.get("metadata.name"))
+                        .map_or(false, |name| name == "default"),
+                    _ => false,
+                };
+
+                if interesting {
+                    let mut state = s.borrow_mut();
+                    state.pending.push(global.to_owned());
+
+                    match global.props.and_then(|props| props.get("media.class")) {
+                        Some(SOURCE_CLASS) => State::send(&state.sender, VolumeEvent::Devices),
+                        Some(STREAM_CLASS) => State::send(&state.sender, VolumeEvent::Streams),
+                        _ => {}
+                    }
+                }
+            })
+            .global_remove({
+                let s = state.clone();
+                move |id| {
+                    let mut state = s.borrow_mut();
+                    if state.nodes.remove(&id).is_some() {
+                        State::send(&state.sender, VolumeEvent::Devices);
+                    }
+                    if state.streams.remove(&id).is_some() {
+                        State::send(&state.sender, VolumeEvent::Streams);
+                    }
+                    state.devices.remove(&id);
+                }
+            })
+            .register();
+
+        let volume = PipeWireVolume {
+            state,
+            _registry_listener: registry_listener,
+            registry,
+            core,
+            _context: context,
+            mainloop,
+        };
+        volume.sync()?;
+
+        Ok(volume)
+    }
+
+    fn roundtrip(&self) -> Result<()> {
+        let done = Rc::new(Cell::new(false));
+        let pending = self.core.sync(0).context("PipeWire sync failed")?;
+
+        let d = done.clone();
+        let _listener = self
+            .core
+            .add_listener_local()
+            .done(move |id, seq| {
+                if id == PW_ID_CORE && seq == pending {
+                    d.set(true);
+                }
+            })
+            .register();
+
+        let deadline = Instant::now() + SYNC_TIMEOUT;
+        while !done.get() {
+            if Instant::now() > deadline {
+                return Err(anyhow::anyhow!("PipeWire daemon not responding"));
+            }
+
+            self.mainloop.loop_().iterate(Duration::from_millis(100));
+        }
+
+        Ok(())
+    }
+
+    /// 等待服务端处理完所有请求, 新出现的对象会在这里 bind 并再次同步以拿到初始状态
+    fn sync(&self) -> Result<()> {
+        loop {
+            self.roundtrip()?;
+
+            if !self.bind_pending()? {
+                return Ok(());
+            }
+        }
+    }
+
+    fn bind_pending(&self) -> Result<bool> {
+        let mut state = self.state.borrow_mut();
+        let pending = std::mem::take(&mut state.pending);
+        let bound = !pending.is_empty();
+
+        for global in pending {
+            match global.type_ {
+                ObjectType::Node => {
+                    let is_stream = media_class(&global) == Some(STREAM_CLASS);
+                    let event = if is_stream {
+                        VolumeEvent::Streams
+                    } else {
+                        VolumeEvent::Mute
+                    };
+
+                    let proxy: Node = self
+                        .registry
+                        .bind(&global)
+                        .context("Bind PipeWire node failed")?;
+
+                    let mute = Rc::new(Cell::new(None));
+                    let volumes = Rc::new(RefCell::new(None));
+                    let route = Rc::new(Cell::new(None));
+                    let m = mute.clone();
+                    let v = volumes.clone();
+                    let r = route.clone();
+                    let sender = state.sender.clone();
+                    let listener = proxy
+                        .add_listener_local()
+                        .info(move |info| {
+                            // 这两个属性不一定出现在 registry 的 global 上
+                            let props = match info.props() {
+                                Some(it) => it,
+                                None => return,
+                            };
+                            let device = props.get("device.id").and_then(|it| it.parse().ok());
+                            let card_device = props
+                                .get("card.profile.device")
+                                .and_then(|it| it.parse().ok());
+                            r.set(device.zip(card_device));
+                        })
+                        .param(move |_seq, id, _index, _next, param| {
+                            if id != ParamType::Props {
+                                return;
+                            }
+
+                            let (mute, volumes) = match param.and_then(parse_props) {
+                                Some(it) => it,
+                                None => return,
+                            };
+
+                            let mut changed = false;
+                            if let Some(value) = mute {
+                                changed |= m.replace(Some(value)) != Some(value);
+                            }
+                            if let Some(value) = volumes {
+                                changed |= v.borrow().as_ref() != Some(&value);
+                                *v.borrow_mut() = Some(value);
+                            }
+
+                            if changed {
+                                State::send(&sender, event.clone());
+                            }
+                        })
+                        .register();
+                    proxy.subscribe_params(&[ParamType::Props]);
+
+                    let props = global.props.as_ref();
+                    let name = props
+                        .and_then(|props| props.get("node.name"))
+                        .unwrap_or_default()
+                        .to_string();
+                    let description = props
+                        .and_then(|props| {
+                            props
+                                .get("node.description")
+                                .or_else(|| props.get("media.name"))
+                                .or_else(|| props.get("node.nick"))
+                        })
+                        .unwrap_or(&name)
+                        .to_string();
+                    let app = props
+                        .and_then(|props| props.get("application.name"))
+                        .unwrap_or(&name)
+                        .to_string();
+
+                    let node = AudioNode {
+                        name,
+                        description,
+                        app,
+                        mute,
+                        volumes,
+                        route,
+                        proxy,
+                        _listener: listener,
+                    };
+                    if is_stream {
+                        state.streams.insert(global.id, node);
+                    } else {
+                        state.nodes.insert(global.id, node);
+                    }
+                }
+                ObjectType::Device => {
+                    let proxy: Device = self
+                        .registry
+                        .bind(&global)
+                        .context("Bind PipeWire device failed")?;
+
+                    let routes = Rc::new(RefCell::new(BTreeMap::new()));
+                    let r = routes.clone();
+                    let listener = proxy
+                        .add_listener_local()
+                        .param(move |_seq, id, _index, _next, param| {
+                            if id != ParamType::Route {
+                                return;
+                            }
+
+                            if let Some((index, card_device)) = param.and_then(parse_route) {
+                                r.borrow_mut().insert(card_device, index);
+                            }
+                        })
+                        .register();
+                    proxy.subscribe_params(&[ParamType::Route]);
+
+                    let device = AudioDevice {
+                        routes,
+                        proxy,
+                        _listener: listener,
+                    };
+                    state.devices.insert(global.id, device);
+                }
+                ObjectType::Metadata => {
+                    let proxy: Metadata = self
+                        .registry
+                        .bind(&global)
+                        .context("Bind PipeWire metadata failed")?;
+
+                    let default_source = state.default_source.clone();
+                    let sender = state.sender.clone();
+                    let listener = proxy
+                        .add_listener_local()
+                        .property(move |_subject, key, _type, value| {
+                            let name = match key {
+                                Some("default.audio.source") => value.and_then(parse_name),
+                                None => None,
+                                _ => return 0,
+                            };
+
+                            if *default_source.borrow() != name {
+                                *default_source.borrow_mut() = name;
+                                State::send(&sender, VolumeEvent::DefaultDevice);
+                            }
+
+                            0
+                        })
+                        .register();
+
+                    state.metadata = Some((proxy, listener));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(bound)
+    }
+
+    fn target_name(&self) -> Result<String> {
+        let state = self.state.borrow();
+        let default_source = state.default_source.borrow();
+
+        state
+            .pinned
+            .clone()
+            .or_else(|| default_source.clone())
+            .context("No default audio source")
+    }
+
+    fn with_node<T>(&self, name: &str, f: impl FnOnce(&AudioNode) -> T) -> Result<T> {
+        self.state.borrow().node(name).map(f)
+    }
+
+    fn with_target_node<T>(&self, f: impl FnOnce(&AudioNode) -> T) -> Result<T> {
+        self.with_node(&self.target_name()?, f)
+    }
+
+    fn set_mute(&self, mute: bool) -> Result<()> {
+        self.set_node_mute(&self.target_name()?, mute)
+    }
+
+    fn set_node_mute(&self, name: &str, mute: bool) -> Result<()> {
+        {
+            let state = self.state.borrow();
+            let node = state.node(name)?;
+            state.set_props(node, Property::new(SPA_PROP_mute, Value::Bool(mute)))?;
+            node.mute.set(Some(mute));
+        }
+
+        self.sync()
+    }
+
+    fn get_volumes(&self) -> Result<Vec<f32>> {
+        self.sync()?;
+
+        self.with_target_node(|node| node.volumes.borrow().clone())?
+            .context("Volume of audio source unknown")
+    }
+
+    /// 所有声道设置为同一个线性音量
+    fn set_volume(&self, volume: f32) -> Result<()> {
+        let name = self.target_name()?;
+        let channels = self.get_volumes()?.len();
+        let volumes = vec![volume; channels];
+
+        {
+            let state = self.state.borrow();
+            let node = state.node(&name)?;
+            state.set_props(
+                node,
+                Property::new(
+                    SPA_PROP_channelVolumes,
+                    Value::ValueArray(ValueArray::Float(volumes.clone())),
+                ),
+            )?;
+            *node.volumes.borrow_mut() = Some(volumes);
+        }
+
+        self.sync()
+    }
+
+    fn get_mute(&self) -> Result<bool> {
+        self.get_node_mute(&self.target_name()?)
+    }
+
+    fn get_node_mute(&self, name: &str) -> Result<bool> {
+        self.sync()?;
+
+        self.with_node(name, |node| node.mute.get())?
+            .context("Mute state of audio source unknown")
+    }
+}
+
+fn serialize(value: &Value) -> Result<Vec<u8>> {
+    let (bytes, _) = PodSerializer::serialize(Cursor::new(Vec::new()), value)
+        .map_err(|_| anyhow::anyhow!("Serialize pod failed"))?;
+
+    Ok(bytes.into_inner())
+}
+
+/// 没有所属声卡的节点直接设置 Props
+fn set_node_props(node: &AudioNode, property: Property) -> Result<()> {
+    let props = Value::Object(Object {
+        type_: SpaTypes::ObjectParamProps.as_raw(),
+        id: ParamType::Props.as_raw(),
+        properties: vec![property],
+    });
+    let bytes = serialize(&props)?;
+    let pod = Pod::from_bytes(&bytes).context("Invalid Props pod")?;
+
+    node.proxy.set_param(ParamType::Props, 0, pod);
+
+    Ok(())
+}
+
+/// 从 Props 中取出静音状态和各声道音量
+fn parse_props(pod: &Pod) -> Option<(Option<bool>, Option<Vec<f32>>)> {
+    let (_, value) = PodDeserializer::deserialize_from::<Value>(pod.as_bytes()).ok()?;
+
+    let object = match value {
+        Value::Object(object) => object,
+        _ => return None,
+    };
+
+    let mut mute = None;
+    let mut volumes = None;
+    for prop in object.properties {
+        match (prop.key, prop.value) {
+            (SPA_PROP_mute, Value::Bool(value)) => mute = Some(value),
+            (SPA_PROP_channelVolumes, Value::ValueArray(ValueArray::Float(value))) => {
+                volumes = Some(value)
+            }
+            _ => {}
+        }
+    }
+
+    Some((mute, volumes))
+}
+
+/// 从 Route 中取出 index 和 card.profile.device
+fn parse_route(pod: &Pod) -> Option<(i32, i32)> {
+    let (_, value) = PodDeserializer::deserialize_from::<Value>(pod.as_bytes()).ok()?;
+
+    let object = match value {
+        Value::Object(object) => object,
+        _ => return None,
+    };
+
+    let mut index = None;
+    let mut device = None;
+    for prop in object.properties {
+        match (prop.key, prop.value) {
+            (SPA_PARAM_ROUTE_index, Value::Int(value)) => index = Some(value),
+            (SPA_PARAM_ROUTE_device, Value::Int(value)) => device = Some(value),
+            _ => {}
+        }
+    }
+
+    index.zip(device)
+}
+
+/// metadata 的值是形如 `{ "name": "alsa_input.xxx" }` 的 JSON, 这里只需要取出 name
+fn parse_name(json: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(json).ok()?;
+
+    value.get("name")?.as_str().map(str::to_string)
+}
+
+impl MicBackend for PipeWireVolume {
+    fn mute(&self) -> bool {
+        self.set_mute(true).is_ok()
+    }
+
+    fn unmute(&self) -> bool {
+        self.set_mute(false).is_ok()
+    }
+
+    fn toggle(&self) {
+        if let Ok(mute) = self.get_mute() {
+            self.set_mute(!mute).ok();
+        }
+    }
+
+    fn is_mute(&self) -> bool {
+        self.get_mute().unwrap_or(false)
+    }
+
+    fn devices(&self) -> Result<Vec<CaptureDevice>> {
+        self.sync()?;
+
+        let state = self.state.borrow();
+        Ok(state.nodes.values().map(AudioNode::capture_device).collect())
+    }
+
+    fn current_device(&self) -> Option<CaptureDevice> {
+        self.with_target_node(AudioNode::capture_device).ok()
+    }
+
+    fn select_device(&self, id: Option<&str>) -> Result<()> {
+        self.sync()?;
+
+        let mut state = self.state.borrow_mut();
+        if let Some(id) = id {
+            if !state.nodes.values().any(|node| node.name == id) {
+                return Err(anyhow::anyhow!("Capture device not found: {}", id));
+            }
+        }
+        state.pinned = id.map(|it| it.to_string());
+
+        Ok(())
+    }
+
+    fn set_device_mute(&self, id: &str, mute: bool) -> Result<()> {
+        self.set_node_mute(id, mute)
+    }
+
+    fn device_mute(&self, id: &str) -> Result<bool> {
+        self.get_node_mute(id)
+    }
+
+    /// channelVolumes 是线性音量, 这里和 pipewire-pulse 一样按立方曲线换算成滑块位置
+    fn gain(&self) -> Result<f32> {
+        let volume = self.get_volumes()?.into_iter().fold(0.0, f32::max);
+
+        Ok(volume.cbrt())
+    }
+
+    fn set_gain(&self, gain: f32) -> Result<()> {
+        self.set_volume(gain.clamp(0.0, 1.0).powi(3))
+    }
+
+    fn gain_db(&self) -> Result<f32> {
+        let volume = self.get_volumes()?.into_iter().fold(0.0, f32::max);
+
+        Ok(amplitude_to_db(volume))
+    }
+
+    fn set_gain_db(&self, db: f32) -> Result<()> {
+        self.set_volume(db_to_amplitude(db).min(1.0))
+    }
+
+    fn gain_range_db(&self) -> Result<(f32, f32)> {
+        Ok(SOFT_GAIN_RANGE_DB)
+    }
+
+    fn streams(&self) -> Result<Vec<CaptureStream>> {
+        self.sync()?;
+
+        let state = self.state.borrow();
+        Ok(state
+            .streams
+            .iter()
+            .map(|(&id, node)| node.capture_stream(id))
+            .collect())
+    }
+
+    fn set_stream_mute(&self, id: u32, mute: bool) -> Result<()> {
+        {
+            let state = self.state.borrow();
+            let node = state
+                .streams
+                .get(&id)
+                .with_context(|| format!("Capture stream not found: {}", id))?;
+
+            state.set_props(node, Property::new(SPA_PROP_mute, Value::Bool(mute)))?;
+            node.mute.set(Some(mute));
+        }
+
+        self.sync()
+    }
+
+    fn subscribe(&self, sender: glib::Sender<VolumeEvent>) -> Result<()> {
+        *self.state.borrow().sender.borrow_mut() = Some(sender);
+
+        // 把 PipeWire 的 loop 挂到 glib 主循环上, 这样不调用任何方法时也能收到事件
+        let mainloop = self.mainloop.clone();
+        let fd = self.mainloop.loop_().fd().as_raw_fd();
+        glib::source::unix_fd_add_local(fd, glib::IOCondition::IN, move |_, _| {
+            mainloop.loop_().iterate(Duration::ZERO);
+            glib::Continue(true)
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::volume::tests::{check_gain, check_mute};
+
+    #[test]
+    fn parse_default_source() {
+        let json = r#"{ "name": "alsa_input.usb-Mic \"Pro\"-00.mono-fallback" }"#;
+        assert_eq!(
+            parse_name(json).as_deref(),
+            Some(r#"alsa_input.usb-Mic "Pro"-00.mono-fallback"#)
+        );
+        assert_eq!(parse_name(r#"{ "name": 1 }"#), None);
+        assert_eq!(parse_name("not json"), None);
+    }
+
+    /// 需要先创建一个 null source 节点, 见 README
+    #[test]
+    #[ignore]
+    fn null_source() {
+        let volume = PipeWireVolume::new().unwrap();
+        volume.select_device(Some("mute_test")).unwrap();
+        assert_eq!(volume.current_device().unwrap().id, "mute_test");
+
+        check_mute(&volume);
+        check_gain(&volume);
+    }
+}
@@ -0,0 +1,422 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use anyhow::{Context as _, Result};
+use libpulse_binding::callbacks::ListResult;
+use libpulse_binding::context::introspect::{SourceInfo, SourceOutputInfo};
+use libpulse_binding::context::subscribe::{
+    Facility, InterestMaskSet, Operation as SubscribeOperation,
+};
+use libpulse_binding::context::{Context, FlagSet, State};
+use libpulse_binding::mainloop::standard::{IterateResult, Mainloop};
+use libpulse_binding::operation::{Operation, State as OperationState};
+use libpulse_binding::proplist::properties::APPLICATION_NAME;
+use libpulse_binding::volume::{ChannelVolumes, Volume, VolumeDB};
+
+use super::{CaptureDevice, CaptureStream, MicBackend, VolumeEvent, SOFT_GAIN_RANGE_DB};
+
+const DEFAULT_SOURCE: &str = "@DEFAULT_SOURCE@";
+
+/// 通过 libpulse 控制 PulseAudio 的默认输入源.
+/// 每次操作都按 `@DEFAULT_SOURCE@` 查找, 所以默认设备改变后也会作用到新的设备上
+pub struct PulseVolume {
+    mainloop: RefCell<Mainloop>,
+    context: Context,
+
+    // 用户固定的 source 名称, 为 None 时使用默认 source
+    pinned: RefCell<Option<String>>,
+}
+
+impl PulseVolume {
+    pub fn new() -> Result<Self> {
+        let (mainloop, context) = connect()?;
+
+        Ok(PulseVolume {
+            mainloop: RefCell::new(mainloop),
+            context,
+            pinned: RefCell::new(None),
+        })
+    }
+
+    fn wait<T: ?Sized>(&self, op: Operation<T>) -> Result<()> {
+        let mut mainloop = self.mainloop.borrow_mut();
+
+        while op.get_state() == OperationState::Running {
+            match mainloop.iterate(true) {
+                IterateResult::Success(_) => {}
+                IterateResult::Quit(_) | IterateResult::Err(_) => {
+                    return Err(anyhow::anyhow!("PulseAudio mainloop failed"));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn target(&self) -> String {
+        self.pinned
+            .borrow()
+            .clone()
+            .unwrap_or_else(|| DEFAULT_SOURCE.to_string())
+    }
+
+    fn set_mute(&self, mute: bool) -> Result<bool> {
+        self.set_source_mute(&self.target(), mute)
+    }
+
+    fn set_source_mute(&self, name: &str, mute: bool) -> Result<bool> {
+        let success = Rc::new(Cell::new(false));
+
+        let s = success.clone();
+        let op = self.context.introspect().set_source_mute_by_name(
+            name,
+            mute,
+            Some(Box::new(move |r| s.set(r))),
+        );
+        self.wait(op)?;
+
+        Ok(success.get())
+    }
+
+    fn get_mute(&self) -> Result<bool> {
+        self.get_source_mute(&self.target())
+    }
+
+    fn get_source_mute(&self, name: &str) -> Result<bool> {
+        let mute = Rc::new(Cell::new(None));
+
+        let m = mute.clone();
+        let op = self
+            .context
+            .introspect()
+            .get_source_info_by_name(name, move |info| {
+                if let ListResult::Item(info) = info {
+                    m.set(Some(info.mute));
+                }
+            });
+        self.wait(op)?;
+
+        mute.get().context("Source not found")
+    }
+
+    fn get_volume(&self) -> Result<ChannelVolumes> {
+        let volume = Rc::new(Cell::new(None));
+
+        let v = volume.clone();
+        let op = self
+            .context
+            .introspect()
+            .get_source_info_by_name(&self.target(), move |info| {
+                if let ListResult::Item(info) = info {
+                    v.set(Some(info.volume));
+                }
+            });
+        self.wait(op)?;
+
+        volume.get().context("Source not found")
+    }
+
+    /// 所有声道设置为同一个音量
+    fn set_volume(&self, volume: Volume) -> Result<()> {
+        let mut channels = self.get_volume()?;
+        let len = channels.len();
+        channels.set(len, volume);
+
+        let success = Rc::new(Cell::new(false));
+
+        let s = success.clone();
+        let op = self.context.introspect().set_source_volume_by_name(
+            &self.target(),
+            &channels,
+            Some(Box::new(move |r| s.set(r))),
+        );
+        self.wait(op)?;
+
+        if !success.get() {
+            return Err(anyhow::anyhow!("Set source volume failed"));
+        }
+
+        Ok(())
+    }
+
+    fn get_source(&self, name: &str) -> Result<Option<CaptureDevice>> {
+        let device = Rc::new(RefCell::new(None));
+
+        let d = device.clone();
+        let op = self
+            .context
+            .introspect()
+            .get_source_info_by_name(name, move |info| {
+                if let ListResult::Item(info) = info {
+                    *d.borrow_mut() = to_capture_device(info);
+                }
+            });
+        self.wait(op)?;
+
+        let device = device.borrow_mut().take();
+        Ok(device)
+    }
+}
+
+fn connect() -> Result<(Mainloop, Context)> {
+    let mut mainloop = Mainloop::new().context("Create PulseAudio mainloop failed")?;
+    let mut context =
+        Context::new(&mainloop, "MuteAssistant").context("Create PulseAudio context failed")?;
+
+    context
+        .connect(None, FlagSet::NOFLAGS, None)
+        .context("Connect to PulseAudio server failed")?;
+
+    loop {
+        match mainloop.iterate(true) {
+            IterateResult::Success(_) => {}
+            IterateResult::Quit(_) | IterateResult::Err(_) => {
+                return Err(anyhow::anyhow!("PulseAudio mainloop failed"));
+            }
+        }
+
+        match context.get_state() {
+            State::Ready => break,
+            State::Failed | State::Terminated => {
+                return Err(anyhow::anyhow!("Connect to PulseAudio server failed"));
+            }
+            _ => {}
+        }
+    }
+
+    Ok((mainloop, context))
+}
+
+/// 订阅需要一直运行 mainloop, 所以在单独的线程里用另一个连接来监听事件
+fn watch(sender: glib::Sender<VolumeEvent>) -> Result<()> {
+    let (mut mainloop, mut context) = connect()?;
+
+    context.set_subscribe_callback(Some(Box::new(move |facility, operation, _index| {
+        let event = match (facility, operation) {
+            // 默认 source 的改变体现为 server 的改变
+            (Some(Facility::Server), _) => VolumeEvent::DefaultDevice,
+            (Some(Facility::Source), Some(SubscribeOperation::New))
+            | (Some(Facility::Source), Some(SubscribeOperation::Removed)) => {
+                VolumeEvent::Devices
+            }
+            // 音量和静音的修改都会产生 Changed, 由接收方重新读取状态
+            (Some(Facility::Source), Some(SubscribeOperation::Changed)) => {
+                VolumeEvent::Mute
+            }
+            (Some(Facility::SourceOutput), _) => VolumeEvent::Streams,
+            _ => return,
+        };
+
+        sender.send(event).ok();
+    })));
+    context.subscribe(
+        InterestMaskSet::SERVER | InterestMaskSet::SOURCE | InterestMaskSet::SOURCE_OUTPUT,
+        |_| {},
+    );
+
+    mainloop
+        .run()
+        .map_err(|_| anyhow::anyhow!("PulseAudio mainloop failed"))?;
+
+    Ok(())
+}
+
+fn to_capture_device(info: &SourceInfo) -> Option<CaptureDevice> {
+    // monitor source 是输出设备的回环, 不算作输入设备
+    if info.monitor_of_sink.is_some() {
+        return None;
+    }
+
+    let id = info.name.as_ref()?.to_string();
+    let name = info
+        .description
+        .as_ref()
+        .map(|it| it.to_string())
+        .unwrap_or_else(|| id.clone());
+
+    Some(CaptureDevice { id, name })
+}
+
+fn to_capture_stream(info: &SourceOutputInfo) -> CaptureStream {
+    let name = info.name.as_ref().map(|it| it.to_string()).unwrap_or_default();
+    let app = info
+        .proplist
+        .get_str(APPLICATION_NAME)
+        .unwrap_or_else(|| name.clone());
+
+    CaptureStream {
+        id: info.index,
+        app,
+        name,
+        muted: info.mute,
+    }
+}
+
+impl Drop for PulseVolume {
+    fn drop(&mut self) {
+        self.context.disconnect();
+    }
+}
+
+impl MicBackend for PulseVolume {
+    fn mute(&self) -> bool {
+        self.set_mute(true).unwrap_or(false)
+    }
+
+    fn unmute(&self) -> bool {
+        self.set_mute(false).unwrap_or(false)
+    }
+
+    fn toggle(&self) {
+        if let Ok(mute) = self.get_mute() {
+            self.set_mute(!mute).ok();
+        }
+    }
+
+    fn is_mute(&self) -> bool {
+        self.get_mute().unwrap_or(false)
+    }
+
+    fn devices(&self) -> Result<Vec<CaptureDevice>> {
+        let devices = Rc::new(RefCell::new(Vec::new()));
+
+        let d = devices.clone();
+        let op = self.context.introspect().get_source_info_list(move |info| {
+            if let ListResult::Item(info) = info {
+                d.borrow_mut().extend(to_capture_device(info));
+            }
+        });
+        self.wait(op)?;
+
+        let devices = devices.replace(Vec::new());
+        Ok(devices)
+    }
+
+    fn current_device(&self) -> Option<CaptureDevice> {
+        self.get_source(&self.target()).ok().flatten()
+    }
+
+    fn select_device(&self, id: Option<&str>) -> Result<()> {
+        if let Some(id) = id {
+            self.get_source(id)?
+                .with_context(|| format!("Capture device not found: {}", id))?;
+        }
+        *self.pinned.borrow_mut() = id.map(|it| it.to_string());
+
+        Ok(())
+    }
+
+    fn set_device_mute(&self, id: &str, mute: bool) -> Result<()> {
+        if !self.set_source_mute(id, mute)? {
+            return Err(anyhow::anyhow!("Set mute of {} failed", id));
+        }
+
+        Ok(())
+    }
+
+    fn device_mute(&self, id: &str) -> Result<bool> {
+        self.get_source_mute(id)
+    }
+
+    fn gain(&self) -> Result<f32> {
+        let volume = self.get_volume()?.max();
+
+        Ok(volume.0 as f32 / Volume::NORMAL.0 as f32)
+    }
+
+    fn set_gain(&self, gain: f32) -> Result<()> {
+        let gain = gain.clamp(0.0, 1.0);
+
+        self.set_volume(Volume((Volume::NORMAL.0 as f32 * gain) as u32))
+    }
+
+    fn gain_db(&self) -> Result<f32> {
+        let VolumeDB(db) = VolumeDB::from(self.get_volume()?.max());
+
+        Ok((db as f32).max(SOFT_GAIN_RANGE_DB.0))
+    }
+
+    fn set_gain_db(&self, db: f32) -> Result<()> {
+        let (min, max) = SOFT_GAIN_RANGE_DB;
+        let volume = if db <= min {
+            Volume::MUTED
+        } else {
+            Volume::from(VolumeDB(db.min(max) as f64))
+        };
+
+        self.set_volume(volume)
+    }
+
+    fn gain_range_db(&self) -> Result<(f32, f32)> {
+        // PulseAudio 的软件音量没有下限, 这里使用统一的范围
+        Ok(SOFT_GAIN_RANGE_DB)
+    }
+
+    fn streams(&self) -> Result<Vec<CaptureStream>> {
+        let streams = Rc::new(RefCell::new(Vec::new()));
+
+        let s = streams.clone();
+        let op = self
+            .context
+            .introspect()
+            .get_source_output_info_list(move |info| {
+                if let ListResult::Item(info) = info {
+                    s.borrow_mut().push(to_capture_stream(info));
+                }
+            });
+        self.wait(op)?;
+
+        let streams = streams.replace(Vec::new());
+        Ok(streams)
+    }
+
+    fn set_stream_mute(&self, id: u32, mute: bool) -> Result<()> {
+        let success = Rc::new(Cell::new(false));
+
+        let s = success.clone();
+        let op = self.context.introspect().set_source_output_mute(
+            id,
+            mute,
+            Some(Box::new(move |r| s.set(r))),
+        );
+        self.wait(op)?;
+
+        if !success.get() {
+            return Err(anyhow::anyhow!("Set mute of source output {} failed", id));
+        }
+
+        Ok(())
+    }
+
+    fn subscribe(&self, sender: glib::Sender<VolumeEvent>) -> Result<()> {
+        std::thread::spawn(move || {
+            if let Err(e) = watch(sender) {
+                eprintln!("PulseAudio event watcher stopped: {:#}", e);
+            }
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::volume::tests::{check_gain, check_mute};
+
+    /// 需要先加载一个 null source, 见 README
+    #[test]
+    #[ignore]
+    fn null_source() {
+        let volume = PulseVolume::new().unwrap();
+        volume.select_device(Some("mute_test")).unwrap();
+        assert_eq!(volume.current_device().unwrap().id, "mute_test");
+
+        check_mute(&volume);
+        check_gain(&volume);
+
+        // 切回默认设备后也能操作
+        volume.select_device(None).unwrap();
+        assert!(volume.current_device().is_some());
+    }
+}
@@ -0,0 +1,542 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+use super::{CaptureDevice, CaptureStream, MicBackend, MuteState, VolumeEvent};
+
+/// mute/unmute/toggle 的作用范围
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScopeMode {
+    /// 只操作当前设备
+    Device,
+    /// 所有输入设备
+    AllInputs,
+    /// 选中程序的录音流, 设备本身保持不变
+    Apps,
+    /// 除豁免列表以外所有程序的录音流
+    Exempt,
+}
+
+impl Default for ScopeMode {
+    fn default() -> Self {
+        ScopeMode::Device
+    }
+}
+
+impl ScopeMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScopeMode::Device => "device",
+            ScopeMode::AllInputs => "all-inputs",
+            ScopeMode::Apps => "apps",
+            ScopeMode::Exempt => "exempt",
+        }
+    }
+}
+
+impl FromStr for ScopeMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "device" => Ok(ScopeMode::Device),
+            "all-inputs" => Ok(ScopeMode::AllInputs),
+            "apps" => Ok(ScopeMode::Apps),
+            "exempt" => Ok(ScopeMode::Exempt),
+            _ => Err(anyhow::anyhow!("unknown mute scope: {}", s)),
+        }
+    }
+}
+
+/// 某一时刻的静音状态, 按住静音松开时原样恢复
+#[derive(Debug, Clone, PartialEq)]
+pub enum MuteSnapshot {
+    Device(bool),
+    /// 每个设备的 id 和是否静音
+    Devices(Vec<(String, bool)>),
+    /// 之后出现的录音流的状态, 以及每个录音流的 id 和是否静音
+    Streams(bool, Vec<(u32, bool)>),
+}
+
+/// 包装一个后端, 按照 `ScopeMode` 决定 mute/unmute/toggle 作用到哪些设备或录音流,
+/// `ScopeMode::Device` 时和原来的后端完全一样
+pub struct MuteScope {
+    inner: Rc<dyn MicBackend>,
+    // 模式, 选中的程序, 豁免列表以及录音流的静音状态都保存在配置里
+    config: Rc<RefCell<Config>>,
+}
+
+impl MuteScope {
+    pub fn new(inner: Rc<dyn MicBackend>, config: Rc<RefCell<Config>>) -> Self {
+        MuteScope { inner, config }
+    }
+
+    pub fn mode(&self) -> ScopeMode {
+        self.config.borrow().current().scope
+    }
+
+    pub fn set_mode(&self, mode: ScopeMode) {
+        self.update_config(|config| config.current_mut().scope = mode);
+    }
+
+    pub fn is_app_selected(&self, app: &str) -> bool {
+        self.config.borrow().apps.contains(app)
+    }
+
+    pub fn set_app_selected(&self, app: &str, selected: bool) {
+        self.update_config(|config| {
+            if selected {
+                config.apps.insert(app.to_string());
+            } else {
+                config.apps.remove(app);
+            }
+        });
+    }
+
+    pub fn is_app_exempt(&self, app: &str) -> bool {
+        self.config.borrow().exempt_apps.contains(app)
+    }
+
+    /// 豁免模式下加入豁免列表的程序立即取消静音
+    pub fn set_app_exempt(&self, app: &str, exempt: bool) {
+        self.update_config(|config| {
+            if exempt {
+                config.exempt_apps.insert(app.to_string());
+            } else {
+                config.exempt_apps.remove(app);
+            }
+        });
+
+        if !exempt || self.mode() != ScopeMode::Exempt {
+            return;
+        }
+
+        let streams = self.inner.streams().unwrap_or_default();
+        for stream in streams.iter().filter(|it| it.app == app && it.muted) {
+            if let Err(e) = self.inner.set_stream_mute(stream.id, false) {
+                eprintln!("Unmute {} failed: {:#}", stream.app, e);
+            }
+        }
+    }
+
+    /// 选中程序和豁免模式下最近一次操作是否为静音, 之后出现的录音流按这个状态处理
+    fn streams_muted(&self) -> bool {
+        self.config.borrow().streams_muted
+    }
+
+    fn set_streams_muted(&self, mute: bool) {
+        if self.streams_muted() != mute {
+            self.update_config(|config| config.streams_muted = mute);
+        }
+    }
+
+    /// 修改后立即写入配置文件
+    fn update_config(&self, f: impl FnOnce(&mut Config)) {
+        let mut config = self.config.borrow_mut();
+        f(&mut config);
+
+        if let Err(e) = config.save() {
+            eprintln!("Save config failed: {:#}", e);
+        }
+    }
+
+    pub fn snapshot(&self) -> MuteSnapshot {
+        match self.mode() {
+            ScopeMode::Device => MuteSnapshot::Device(self.inner.is_mute()),
+            ScopeMode::AllInputs => MuteSnapshot::Devices(
+                self.inner
+                    .devices()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|device| {
+                        let mute = self.inner.device_mute(&device.id).ok()?;
+                        Some((device.id, mute))
+                    })
+                    .collect(),
+            ),
+            ScopeMode::Apps | ScopeMode::Exempt => MuteSnapshot::Streams(
+                self.streams_muted(),
+                self.target_streams().iter().map(|it| (it.id, it.muted)).collect(),
+            ),
+        }
+    }
+
+    /// 期间消失的设备和录音流忽略, 新出现的录音流按快照时的状态处理
+    pub fn restore(&self, snapshot: &MuteSnapshot) {
+        match snapshot {
+            MuteSnapshot::Device(true) => {
+                self.inner.mute();
+            }
+            MuteSnapshot::Device(false) => {
+                self.inner.unmute();
+            }
+            MuteSnapshot::Devices(devices) => {
+                for (id, mute) in devices {
+                    if let Err(e) = self.inner.set_device_mute(id, *mute) {
+                        eprintln!("Restore mute of {} failed: {:#}", id, e);
+                    }
+                }
+            }
+            MuteSnapshot::Streams(muted, streams) => {
+                self.set_streams_muted(*muted);
+
+                for stream in self.target_streams() {
+                    let mute = streams
+                        .iter()
+                        .find(|it| it.0 == stream.id)
+                        .map_or(*muted, |it| it.1);
+                    if stream.muted == mute {
+                        continue;
+                    }
+
+                    if let Err(e) = self.inner.set_stream_mute(stream.id, mute) {
+                        eprintln!("Restore mute of {} failed: {:#}", stream.app, e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// 录音流变化后调用, 让新出现的流和当前的静音状态一致
+    pub fn sync_streams(&self) {
+        let by_streams = matches!(self.mode(), ScopeMode::Apps | ScopeMode::Exempt);
+        if by_streams && self.streams_muted() {
+            self.set_streams(true);
+        }
+    }
+
+    fn set_all(&self, mute: bool) -> bool {
+        let devices = match self.inner.devices() {
+            Ok(it) => it,
+            Err(e) => {
+                eprintln!("List capture devices failed: {:#}", e);
+                return false;
+            }
+        };
+
+        let mut success = true;
+        for device in devices {
+            if let Err(e) = self.inner.set_device_mute(&device.id, mute) {
+                eprintln!("Set mute of {} failed: {:#}", device.name, e);
+                success = false;
+            }
+        }
+
+        success
+    }
+
+    /// 当前模式下要操作的录音流
+    fn target_streams(&self) -> Vec<CaptureStream> {
+        let streams = self.inner.streams().unwrap_or_default();
+        let mode = self.mode();
+        let config = self.config.borrow();
+
+        streams
+            .into_iter()
+            .filter(|stream| match mode {
+                ScopeMode::Exempt => !config.exempt_apps.contains(&stream.app),
+                _ => config.apps.contains(&stream.app),
+            })
+            .collect()
+    }
+
+    fn set_streams(&self, mute: bool) -> bool {
+        self.set_streams_muted(mute);
+
+        let mut success = true;
+        for stream in self.target_streams() {
+            if stream.muted == mute {
+                continue;
+            }
+
+            if let Err(e) = self.inner.set_stream_mute(stream.id, mute) {
+                eprintln!("Set mute of {} failed: {:#}", stream.app, e);
+                success = false;
+            }
+        }
+
+        success
+    }
+}
+
+fn aggregate(states: &[bool]) -> MuteState {
+    if states.iter().all(|&mute| mute) {
+        MuteState::Muted
+    } else if states.iter().all(|&mute| !mute) {
+        MuteState::Live
+    } else {
+        MuteState::Partial
+    }
+}
+
+impl MicBackend for MuteScope {
+    fn mute(&self) -> bool {
+        match self.mode() {
+            ScopeMode::Device => self.inner.mute(),
+            ScopeMode::AllInputs => self.set_all(true),
+            ScopeMode::Apps | ScopeMode::Exempt => self.set_streams(true),
+        }
+    }
+
+    fn unmute(&self) -> bool {
+        match self.mode() {
+            ScopeMode::Device => self.inner.unmute(),
+            ScopeMode::AllInputs => self.set_all(false),
+            ScopeMode::Apps | ScopeMode::Exempt => self.set_streams(false),
+        }
+    }
+
+    fn toggle(&self) {
+        if self.mode() == ScopeMode::Device {
+            self.inner.toggle();
+            return;
+        }
+
+        // 部分静音时先全部静音, 再按一次才全部取消
+        if self.mute_state() == MuteState::Muted {
+            self.unmute();
+        } else {
+            self.mute();
+        }
+    }
+
+    fn is_mute(&self) -> bool {
+        self.mute_state() == MuteState::Muted
+    }
+
+    fn mute_state(&self) -> MuteState {
+        match self.mode() {
+            ScopeMode::Device => self.inner.mute_state(),
+            ScopeMode::AllInputs => {
+                // 无法读取状态的设备不参与统计
+                let states: Vec<bool> = self
+                    .inner
+                    .devices()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|device| self.inner.device_mute(&device.id).ok())
+                    .collect();
+
+                if states.is_empty() {
+                    return MuteState::Live;
+                }
+                aggregate(&states)
+            }
+            ScopeMode::Apps | ScopeMode::Exempt => {
+                let states: Vec<bool> = self.target_streams().iter().map(|it| it.muted).collect();
+
+                // 没有正在录音的程序时显示最近一次操作的结果
+                if states.is_empty() {
+                    return aggregate(&[self.streams_muted()]);
+                }
+                aggregate(&states)
+            }
+        }
+    }
+
+    fn devices(&self) -> Result<Vec<CaptureDevice>> {
+        self.inner.devices()
+    }
+
+    fn current_device(&self) -> Option<CaptureDevice> {
+        self.inner.current_device()
+    }
+
+    fn select_device(&self, id: Option<&str>) -> Result<()> {
+        self.inner.select_device(id)
+    }
+
+    fn set_device_mute(&self, id: &str, mute: bool) -> Result<()> {
+        self.inner.set_device_mute(id, mute)
+    }
+
+    fn device_mute(&self, id: &str) -> Result<bool> {
+        self.inner.device_mute(id)
+    }
+
+    fn gain(&self) -> Result<f32> {
+        self.inner.gain()
+    }
+
+    fn set_gain(&self, gain: f32) -> Result<()> {
+        self.inner.set_gain(gain)
+    }
+
+    fn gain_db(&self) -> Result<f32> {
+        self.inner.gain_db()
+    }
+
+    fn set_gain_db(&self, db: f32) -> Result<()> {
+        self.inner.set_gain_db(db)
+    }
+
+    fn gain_range_db(&self) -> Result<(f32, f32)> {
+        self.inner.gain_range_db()
+    }
+
+    fn streams(&self) -> Result<Vec<CaptureStream>> {
+        self.inner.streams()
+    }
+
+    fn set_stream_mute(&self, id: u32, mute: bool) -> Result<()> {
+        self.inner.set_stream_mute(id, mute)
+    }
+
+    fn subscribe(&self, sender: glib::Sender<VolumeEvent>) -> Result<()> {
+        self.inner.subscribe(sender)
+    }
+
+    fn rebind(&self) -> Result<()> {
+        self.inner.rebind()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::volume::MockVolume;
+
+    fn new_scope(mode: ScopeMode, mock: MockVolume) -> (Rc<MockVolume>, MuteScope) {
+        let mut config = Config::default();
+        config.current_mut().scope = mode;
+        config.apps.insert("zoom".to_string());
+        config.exempt_apps.insert("obs".to_string());
+
+        let mock = Rc::new(mock);
+        let scope = MuteScope::new(mock.clone(), Rc::new(RefCell::new(config)));
+        (mock, scope)
+    }
+
+    fn two_devices() -> MockVolume {
+        MockVolume::with_devices(&[("usb", "USB Microphone"), ("hdmi", "HDMI Input")])
+    }
+
+    fn stream_muted(mock: &MockVolume, id: u32) -> bool {
+        mock.streams().unwrap().iter().find(|it| it.id == id).unwrap().muted
+    }
+
+    #[test]
+    fn device_mode_uses_current_device() {
+        let (mock, scope) = new_scope(ScopeMode::Device, two_devices());
+
+        scope.toggle();
+        assert!(mock.device_mute("usb").unwrap());
+        assert!(!mock.device_mute("hdmi").unwrap());
+        assert_eq!(scope.mute_state(), MuteState::Muted);
+    }
+
+    #[test]
+    fn all_inputs_partial_then_muted() {
+        let (mock, scope) = new_scope(ScopeMode::AllInputs, two_devices());
+        mock.set_device_mute("hdmi", true).unwrap();
+        assert_eq!(scope.mute_state(), MuteState::Partial);
+
+        // 部分静音时先全部静音
+        scope.toggle();
+        assert_eq!(scope.mute_state(), MuteState::Muted);
+        scope.toggle();
+        assert!(!mock.device_mute("usb").unwrap());
+        assert!(!mock.device_mute("hdmi").unwrap());
+    }
+
+    #[test]
+    fn apps_mode_mutes_selected_streams() {
+        let (mock, scope) = new_scope(ScopeMode::Apps, MockVolume::new());
+        mock.add_stream(1, "zoom");
+        mock.add_stream(2, "firefox");
+
+        scope.mute();
+        assert!(stream_muted(&mock, 1));
+        assert!(!stream_muted(&mock, 2));
+        assert!(!mock.is_mute());
+
+        // 静音期间开始录音的程序也静音
+        mock.add_stream(3, "zoom");
+        scope.sync_streams();
+        assert!(stream_muted(&mock, 3));
+    }
+
+    #[test]
+    fn exempt_app_is_unmuted() {
+        let (mock, scope) = new_scope(ScopeMode::Exempt, MockVolume::new());
+        mock.add_stream(1, "zoom");
+        mock.add_stream(2, "obs");
+
+        scope.mute();
+        assert!(stream_muted(&mock, 1));
+        assert!(!stream_muted(&mock, 2));
+
+        scope.set_app_exempt("zoom", true);
+        assert!(!stream_muted(&mock, 1));
+        assert_eq!(scope.mute_state(), MuteState::Muted);
+    }
+
+    #[test]
+    fn streams_muted_survives_restart() {
+        let (_, scope) = new_scope(ScopeMode::Apps, MockVolume::new());
+        scope.mute();
+        assert_eq!(scope.mute_state(), MuteState::Muted);
+
+        let mock = Rc::new(MockVolume::new());
+        let config = Rc::new(RefCell::new(Config::load().unwrap()));
+        let scope = MuteScope::new(mock.clone(), config);
+        mock.add_stream(1, "zoom");
+        scope.sync_streams();
+        assert!(stream_muted(&mock, 1));
+    }
+
+    #[test]
+    fn restore_device_snapshot() {
+        for &muted in &[false, true] {
+            let (mock, scope) = new_scope(ScopeMode::Device, MockVolume::new());
+            mock.set_device_mute("mock", muted).unwrap();
+
+            let snapshot = scope.snapshot();
+            scope.mute();
+            // 按住期间切换过静音也恢复到按下时的状态
+            scope.toggle();
+            scope.restore(&snapshot);
+            assert_eq!(mock.is_mute(), muted);
+        }
+    }
+
+    #[test]
+    fn restore_all_inputs_snapshot() {
+        let (mock, scope) = new_scope(ScopeMode::AllInputs, two_devices());
+        mock.set_device_mute("hdmi", true).unwrap();
+
+        let snapshot = scope.snapshot();
+        scope.mute();
+        assert_eq!(scope.mute_state(), MuteState::Muted);
+
+        scope.restore(&snapshot);
+        assert!(!mock.device_mute("usb").unwrap());
+        assert!(mock.device_mute("hdmi").unwrap());
+    }
+
+    #[test]
+    fn restore_streams_snapshot() {
+        let (mock, scope) = new_scope(ScopeMode::Apps, MockVolume::new());
+        mock.add_stream(1, "zoom");
+        mock.add_stream(2, "zoom");
+        mock.set_stream_mute(1, true).unwrap();
+
+        let snapshot = scope.snapshot();
+        scope.mute();
+        mock.add_stream(3, "zoom");
+        scope.sync_streams();
+        assert!(stream_muted(&mock, 3));
+
+        // 按下后才出现的录音流按按下时的状态处理
+        scope.restore(&snapshot);
+        assert!(stream_muted(&mock, 1));
+        assert!(!stream_muted(&mock, 2));
+        assert!(!stream_muted(&mock, 3));
+    }
+}
@@ -0,0 +1,442 @@
+use anyhow::Result;
+
+use super::{CaptureDevice, MicBackend, VolumeEvent};
+
+mod notify;
+
+use notify::Callback;
+
+use winapi::um::combaseapi::*;
+use winapi::um::coml2api::STGM_READ;
+use winapi::um::endpointvolume::*;
+use winapi::um::functiondiscoverykeys_devpkey::PKEY_Device_FriendlyName;
+use winapi::um::mmdeviceapi::*;
+use winapi::um::propidl::PROPVARIANT;
+use winapi::um::propsys::IPropertyStore;
+
+use winapi::shared::winerror::FAILED;
+use winapi::shared::wtypes::VT_LPWSTR;
+use winapi::um::objbase::CoInitialize;
+use winapi::um::unknwnbase::IUnknown;
+use winapi::Interface;
+
+use std::cell::{Cell, RefCell};
+use std::ffi::OsString;
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::ptr;
+use std::ptr::NonNull;
+
+macro_rules! check_result {
+    ( $result:ident, $reason:expr ) => {
+        if FAILED($result) {
+            return Err(anyhow::anyhow!($reason));
+        }
+    };
+}
+
+pub struct Volume {
+    enumerator: NonNull<IMMDeviceEnumerator>,
+
+    device: Cell<NonNull<IMMDevice>>,
+
+    volume_endpoint: Cell<NonNull<IAudioEndpointVolume>>,
+
+    // 用户固定的设备 id, 为 None 时使用系统默认设备
+    pinned: RefCell<Option<String>>,
+
+    notification: Cell<Option<NonNull<IMMNotificationClient>>>,
+
+    // 注册在当前 volume_endpoint 上的回调, 切换设备时需要重新注册
+    volume_callback: Cell<Option<NonNull<IAudioEndpointVolumeCallback>>>,
+    sender: RefCell<Option<glib::Sender<VolumeEvent>>>,
+}
+
+impl Volume {
+    pub fn new() -> Result<Self> {
+        let r = unsafe { CoInitialize(ptr::null_mut()) };
+        check_result!(r, "CoInitialize failed");
+
+        let mut enumerator = ptr::null_mut();
+        let r = unsafe {
+            CoCreateInstance(
+                &CLSID_MMDeviceEnumerator,
+                ptr::null_mut(),
+                CLSCTX_ALL,
+                &IMMDeviceEnumerator::uuidof(),
+                &mut enumerator,
+            )
+        };
+        check_result!(r, "Create IMMDeviceEnumerator instance failed");
+        let enumerator: NonNull<IMMDeviceEnumerator> =
+            NonNull::new(enumerator as *mut IMMDeviceEnumerator).unwrap();
+
+        let device = default_device(enumerator)?;
+        let volume_endpoint = activate(device)?;
+
+        Ok(Volume {
+            enumerator,
+            device: Cell::new(device),
+            volume_endpoint: Cell::new(volume_endpoint),
+            pinned: RefCell::new(None),
+            notification: Cell::new(None),
+            volume_callback: Cell::new(None),
+            sender: RefCell::new(None),
+        })
+    }
+
+    fn endpoint(&self) -> &IAudioEndpointVolume {
+        unsafe { self.volume_endpoint.get().as_ref() }
+    }
+
+    fn get_device(&self, id: &str) -> Result<NonNull<IMMDevice>> {
+        let id = to_wide(id);
+        let mut device = ptr::null_mut();
+        let r = unsafe { self.enumerator.as_ref().GetDevice(id.as_ptr(), &mut device) };
+        check_result!(r, "Capture device not found");
+
+        Ok(NonNull::new(device).unwrap())
+    }
+
+    /// 临时打开另一个设备的 IAudioEndpointVolume, 用完后释放
+    fn with_device_endpoint<T>(
+        &self,
+        id: &str,
+        f: impl FnOnce(&IAudioEndpointVolume) -> Result<T>,
+    ) -> Result<T> {
+        let device = self.get_device(id)?;
+        let volume_endpoint = match activate(device) {
+            Ok(it) => it,
+            Err(e) => {
+                release(device);
+                return Err(e);
+            }
+        };
+
+        let result = f(unsafe { volume_endpoint.as_ref() });
+
+        release(volume_endpoint);
+        release(device);
+
+        result
+    }
+
+    /// 切换到另一个设备, 释放之前的设备和 IAudioEndpointVolume
+    fn bind(&self, device: NonNull<IMMDevice>) -> Result<()> {
+        let volume_endpoint = match activate(device) {
+            Ok(it) => it,
+            Err(e) => {
+                release(device);
+                return Err(e);
+            }
+        };
+
+        self.unwatch_endpoint();
+        release(self.volume_endpoint.replace(volume_endpoint));
+        release(self.device.replace(device));
+
+        self.watch_endpoint()
+    }
+
+    /// 在当前设备上注册静音状态的回调, 还没有订阅时什么也不做
+    fn watch_endpoint(&self) -> Result<()> {
+        let sender = match self.sender.borrow().clone() {
+            Some(sender) => sender,
+            None => return Ok(()),
+        };
+
+        let callback = NonNull::new(Callback::volume_callback(sender)).unwrap();
+        let r = unsafe { self.endpoint().RegisterControlChangeNotify(callback.as_ptr()) };
+        if FAILED(r) {
+            release(callback);
+            return Err(anyhow::anyhow!("RegisterControlChangeNotify failed"));
+        }
+        self.volume_callback.set(Some(callback));
+
+        Ok(())
+    }
+
+    fn unwatch_endpoint(&self) {
+        if let Some(callback) = self.volume_callback.take() {
+            unsafe { self.endpoint().UnregisterControlChangeNotify(callback.as_ptr()) };
+            release(callback);
+        }
+    }
+}
+
+impl Drop for Volume {
+    fn drop(&mut self) {
+        if let Some(notification) = self.notification.take() {
+            unsafe {
+                self.enumerator
+                    .as_ref()
+                    .UnregisterEndpointNotificationCallback(notification.as_ptr());
+            }
+            release(notification);
+        }
+
+        self.unwatch_endpoint();
+        release(self.volume_endpoint.get());
+        release(self.device.get());
+        release(self.enumerator);
+    }
+}
+
+fn release<T: Interface>(ptr: NonNull<T>) {
+    unsafe {
+        (*(ptr.as_ptr() as *mut IUnknown)).Release();
+    }
+}
+
+fn to_wide(s: &str) -> Vec<u16> {
+    std::ffi::OsStr::new(s)
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect()
+}
+
+unsafe fn from_wide(s: *const u16) -> String {
+    let len = (0..).take_while(|&i| *s.offset(i) != 0).count();
+    let slice = std::slice::from_raw_parts(s, len);
+
+    OsString::from_wide(slice).to_string_lossy().into_owned()
+}
+
+fn default_device(enumerator: NonNull<IMMDeviceEnumerator>) -> Result<NonNull<IMMDevice>> {
+    let mut device = ptr::null_mut();
+    let r = unsafe {
+        enumerator
+            .as_ref()
+            .GetDefaultAudioEndpoint(eCapture, eConsole, &mut device)
+    };
+    check_result!(r, "GetDefaultAudioEndpoint failed");
+
+    Ok(NonNull::new(device).unwrap())
+}
+
+fn activate(device: NonNull<IMMDevice>) -> Result<NonNull<IAudioEndpointVolume>> {
+    let mut volume_endpoint = ptr::null_mut();
+    let r = unsafe {
+        device.as_ref().Activate(
+            &IAudioEndpointVolume::uuidof(),
+            CLSCTX_ALL,
+            ptr::null_mut(),
+            &mut volume_endpoint,
+        )
+    };
+    check_result!(r, "Create IAudioEndpointVolume instance failed");
+
+    Ok(NonNull::new(volume_endpoint as *mut IAudioEndpointVolume).unwrap())
+}
+
+fn device_id(device: NonNull<IMMDevice>) -> Result<String> {
+    let mut id = ptr::null_mut();
+    let r = unsafe { device.as_ref().GetId(&mut id) };
+    check_result!(r, "Get device id failed");
+
+    let result = unsafe { from_wide(id) };
+    unsafe { CoTaskMemFree(id as *mut _) };
+
+    Ok(result)
+}
+
+fn friendly_name(device: NonNull<IMMDevice>) -> Result<String> {
+    let mut store: *mut IPropertyStore = ptr::null_mut();
+    let r = unsafe { device.as_ref().OpenPropertyStore(STGM_READ, &mut store) };
+    check_result!(r, "Open device property store failed");
+    let store = NonNull::new(store).unwrap();
+
+    let mut value = PROPVARIANT::default();
+    let r = unsafe { store.as_ref().GetValue(&PKEY_Device_FriendlyName, &mut value) };
+
+    let name = if FAILED(r) || value.vt as u32 != VT_LPWSTR {
+        None
+    } else {
+        Some(unsafe { from_wide(*value.data.pwszVal()) })
+    };
+
+    unsafe { PropVariantClear(&mut value) };
+    release(store);
+
+    name.ok_or_else(|| anyhow::anyhow!("Get device friendly name failed"))
+}
+
+fn capture_device(device: NonNull<IMMDevice>) -> Result<CaptureDevice> {
+    Ok(CaptureDevice {
+        id: device_id(device)?,
+        name: friendly_name(device)?,
+    })
+}
+
+impl MicBackend for Volume {
+    fn mute(&self) -> bool {
+        let r = unsafe { self.endpoint().SetMute(1, ptr::null_mut()) };
+        !FAILED(r)
+    }
+
+    fn unmute(&self) -> bool {
+        let r = unsafe { self.endpoint().SetMute(0, ptr::null_mut()) };
+        !FAILED(r)
+    }
+
+    fn toggle(&self) {
+        let mut m = 0;
+
+        unsafe {
+            self.endpoint().GetMute(&mut m);
+            self.endpoint().SetMute((m == 0) as i32, ptr::null_mut());
+        }
+    }
+
+    fn is_mute(&self) -> bool {
+        let mut m = 0;
+        unsafe {
+            self.endpoint().GetMute(&mut m);
+        }
+
+        m == 1
+    }
+
+    fn devices(&self) -> Result<Vec<CaptureDevice>> {
+        let mut collection: *mut IMMDeviceCollection = ptr::null_mut();
+        let r = unsafe {
+            self.enumerator.as_ref().EnumAudioEndpoints(
+                eCapture,
+                DEVICE_STATE_ACTIVE,
+                &mut collection,
+            )
+        };
+        check_result!(r, "EnumAudioEndpoints failed");
+        let collection = NonNull::new(collection).unwrap();
+
+        let mut count = 0;
+        unsafe { collection.as_ref().GetCount(&mut count) };
+
+        let mut devices = Vec::new();
+        for i in 0..count {
+            let mut device = ptr::null_mut();
+            let r = unsafe { collection.as_ref().Item(i, &mut device) };
+            if FAILED(r) {
+                continue;
+            }
+
+            let device = NonNull::new(device).unwrap();
+            if let Ok(it) = capture_device(device) {
+                devices.push(it);
+            }
+            release(device);
+        }
+        release(collection);
+
+        Ok(devices)
+    }
+
+    fn current_device(&self) -> Option<CaptureDevice> {
+        capture_device(self.device.get()).ok()
+    }
+
+    fn select_device(&self, id: Option<&str>) -> Result<()> {
+        let device = match id {
+            Some(id) => self.get_device(id)?,
+            None => default_device(self.enumerator)?,
+        };
+
+        self.bind(device)?;
+        *self.pinned.borrow_mut() = id.map(|it| it.to_string());
+
+        Ok(())
+    }
+
+    fn set_device_mute(&self, id: &str, mute: bool) -> Result<()> {
+        self.with_device_endpoint(id, |volume_endpoint| {
+            let r = unsafe { volume_endpoint.SetMute(mute as i32, ptr::null_mut()) };
+            check_result!(r, "SetMute failed");
+
+            Ok(())
+        })
+    }
+
+    fn device_mute(&self, id: &str) -> Result<bool> {
+        self.with_device_endpoint(id, |volume_endpoint| {
+            let mut m = 0;
+            let r = unsafe { volume_endpoint.GetMute(&mut m) };
+            check_result!(r, "GetMute failed");
+
+            Ok(m == 1)
+        })
+    }
+
+    fn gain(&self) -> Result<f32> {
+        let mut gain = 0.0;
+        let r = unsafe { self.endpoint().GetMasterVolumeLevelScalar(&mut gain) };
+        check_result!(r, "GetMasterVolumeLevelScalar failed");
+
+        Ok(gain)
+    }
+
+    fn set_gain(&self, gain: f32) -> Result<()> {
+        let gain = gain.clamp(0.0, 1.0);
+        let r = unsafe { self.endpoint().SetMasterVolumeLevelScalar(gain, ptr::null_mut()) };
+        check_result!(r, "SetMasterVolumeLevelScalar failed");
+
+        Ok(())
+    }
+
+    fn gain_db(&self) -> Result<f32> {
+        let mut db = 0.0;
+        let r = unsafe { self.endpoint().GetMasterVolumeLevel(&mut db) };
+        check_result!(r, "GetMasterVolumeLevel failed");
+
+        Ok(db)
+    }
+
+    fn set_gain_db(&self, db: f32) -> Result<()> {
+        let (min, max) = self.gain_range_db()?;
+        let db = db.clamp(min, max);
+        let r = unsafe { self.endpoint().SetMasterVolumeLevel(db, ptr::null_mut()) };
+        check_result!(r, "SetMasterVolumeLevel failed");
+
+        Ok(())
+    }
+
+    fn gain_range_db(&self) -> Result<(f32, f32)> {
+        let (mut min, mut max, mut step) = (0.0, 0.0, 0.0);
+        let r = unsafe { self.endpoint().GetVolumeRange(&mut min, &mut max, &mut step) };
+        check_result!(r, "GetVolumeRange failed");
+
+        Ok((min, max))
+    }
+
+    fn subscribe(&self, sender: glib::Sender<VolumeEvent>) -> Result<()> {
+        let notification = NonNull::new(Callback::notification_client(sender.clone())).unwrap();
+        let r = unsafe {
+            self.enumerator
+                .as_ref()
+                .RegisterEndpointNotificationCallback(notification.as_ptr())
+        };
+        if FAILED(r) {
+            release(notification);
+            return Err(anyhow::anyhow!("RegisterEndpointNotificationCallback failed"));
+        }
+
+        if let Some(old) = self.notification.replace(Some(notification)) {
+            unsafe {
+                self.enumerator
+                    .as_ref()
+                    .UnregisterEndpointNotificationCallback(old.as_ptr());
+            }
+            release(old);
+        }
+
+        self.unwatch_endpoint();
+        *self.sender.borrow_mut() = Some(sender);
+        self.watch_endpoint()
+    }
+
+    fn rebind(&self) -> Result<()> {
+        if self.pinned.borrow().is_some() {
+            return Ok(());
+        }
+
+        self.bind(default_device(self.enumerator)?)
+    }
+}
@@ -0,0 +1,183 @@
+use std::ffi::c_void;
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use winapi::shared::guiddef::{IsEqualIID, GUID, REFIID};
+use winapi::shared::minwindef::{DWORD, ULONG};
+use winapi::shared::ntdef::{HRESULT, LPCWSTR};
+use winapi::shared::winerror::{E_NOINTERFACE, S_OK};
+use winapi::shared::wtypes::PROPERTYKEY;
+use winapi::um::endpointvolume::{
+    IAudioEndpointVolumeCallback, IAudioEndpointVolumeCallbackVtbl,
+    PAUDIO_VOLUME_NOTIFICATION_DATA,
+};
+use winapi::um::mmdeviceapi::*;
+use winapi::um::unknwnbase::{IUnknown, IUnknownVtbl};
+use winapi::Interface;
+
+use crate::volume::VolumeEvent;
+
+/// IMMNotificationClient 和 IAudioEndpointVolumeCallback 的实现.
+/// 回调在 COM 的工作线程中执行, 这里只负责把事件通过 glib channel 发回主线程
+#[repr(C)]
+pub struct Callback {
+    vtbl: *const c_void,
+    refs: AtomicU32,
+
+    iid: GUID,
+    sender: glib::Sender<VolumeEvent>,
+}
+
+impl Callback {
+    /// 返回的对象引用计数为 1, 不再使用时需要调用 Release
+    pub fn notification_client(sender: glib::Sender<VolumeEvent>) -> *mut IMMNotificationClient {
+        Self::create(
+            &NOTIFICATION_CLIENT_VTBL as *const _ as *const c_void,
+            IMMNotificationClient::uuidof(),
+            sender,
+        ) as *mut IMMNotificationClient
+    }
+
+    /// 返回的对象引用计数为 1, 不再使用时需要调用 Release
+    pub fn volume_callback(
+        sender: glib::Sender<VolumeEvent>,
+    ) -> *mut IAudioEndpointVolumeCallback {
+        Self::create(
+            &VOLUME_CALLBACK_VTBL as *const _ as *const c_void,
+            IAudioEndpointVolumeCallback::uuidof(),
+            sender,
+        ) as *mut IAudioEndpointVolumeCallback
+    }
+
+    fn create(
+        vtbl: *const c_void,
+        iid: GUID,
+        sender: glib::Sender<VolumeEvent>,
+    ) -> *mut Callback {
+        let callback = Box::new(Callback {
+            vtbl,
+            refs: AtomicU32::new(1),
+            iid,
+            sender,
+        });
+
+        Box::into_raw(callback)
+    }
+
+    unsafe fn from_this<'a, T>(this: *mut T) -> &'a Callback {
+        &*(this as *const Callback)
+    }
+
+    fn send(&self, event: VolumeEvent) {
+        self.sender.send(event).ok();
+    }
+}
+
+static NOTIFICATION_CLIENT_VTBL: IMMNotificationClientVtbl = IMMNotificationClientVtbl {
+    parent: IUnknownVtbl {
+        QueryInterface: query_interface,
+        AddRef: add_ref,
+        Release: release,
+    },
+    OnDeviceStateChanged: on_device_state_changed,
+    OnDeviceAdded: on_device_added,
+    OnDeviceRemoved: on_device_removed,
+    OnDefaultDeviceChanged: on_default_device_changed,
+    OnPropertyValueChanged: on_property_value_changed,
+};
+
+static VOLUME_CALLBACK_VTBL: IAudioEndpointVolumeCallbackVtbl = IAudioEndpointVolumeCallbackVtbl {
+    parent: IUnknownVtbl {
+        QueryInterface: query_interface,
+        AddRef: add_ref,
+        Release: release,
+    },
+    OnNotify: on_notify,
+};
+
+unsafe extern "system" fn query_interface(
+    this: *mut IUnknown,
+    riid: REFIID,
+    ppv: *mut *mut c_void,
+) -> HRESULT {
+    if IsEqualIID(&*riid, &IUnknown::uuidof())
+        || IsEqualIID(&*riid, &Callback::from_this(this).iid)
+    {
+        add_ref(this);
+        *ppv = this as *mut c_void;
+        S_OK
+    } else {
+        *ppv = ptr::null_mut();
+        E_NOINTERFACE
+    }
+}
+
+unsafe extern "system" fn add_ref(this: *mut IUnknown) -> ULONG {
+    Callback::from_this(this).refs.fetch_add(1, Ordering::SeqCst) + 1
+}
+
+unsafe extern "system" fn release(this: *mut IUnknown) -> ULONG {
+    let refs = Callback::from_this(this).refs.fetch_sub(1, Ordering::SeqCst) - 1;
+
+    if refs == 0 {
+        drop(Box::from_raw(this as *mut Callback));
+    }
+
+    refs
+}
+
+unsafe extern "system" fn on_device_state_changed(
+    this: *mut IMMNotificationClient,
+    _device_id: LPCWSTR,
+    _new_state: DWORD,
+) -> HRESULT {
+    Callback::from_this(this).send(VolumeEvent::Devices);
+    S_OK
+}
+
+unsafe extern "system" fn on_device_added(
+    this: *mut IMMNotificationClient,
+    _device_id: LPCWSTR,
+) -> HRESULT {
+    Callback::from_this(this).send(VolumeEvent::Devices);
+    S_OK
+}
+
+unsafe extern "system" fn on_device_removed(
+    this: *mut IMMNotificationClient,
+    _device_id: LPCWSTR,
+) -> HRESULT {
+    Callback::from_this(this).send(VolumeEvent::Devices);
+    S_OK
+}
+
+unsafe extern "system" fn on_default_device_changed(
+    this: *mut IMMNotificationClient,
+    flow: EDataFlow,
+    role: ERole,
+    _device_id: LPCWSTR,
+) -> HRESULT {
+    // 每个 role 都会通知一次, 只关心 Volume 使用的 eConsole
+    if flow == eCapture && role == eConsole {
+        Callback::from_this(this).send(VolumeEvent::DefaultDevice);
+    }
+
+    S_OK
+}
+
+unsafe extern "system" fn on_property_value_changed(
+    _this: *mut IMMNotificationClient,
+    _device_id: LPCWSTR,
+    _key: PROPERTYKEY,
+) -> HRESULT {
+    S_OK
+}
+
+unsafe extern "system" fn on_notify(
+    this: *mut IAudioEndpointVolumeCallback,
+    _notify: PAUDIO_VOLUME_NOTIFICATION_DATA,
+) -> HRESULT {
+    // 自己调用 SetMute 也会收到通知, 接收方重新读取状态即可, 不需要区分来源
+    Callback::from_this(this).send(VolumeEvent::Mute);
+    S_OK
+}